@@ -0,0 +1,138 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use musicsearch::{AudioFile, MusicIndex, ShowField};
+use tantivy::{collector::TopDocs, schema::Field, DocAddress, TantivyDocument};
+
+const LIBRARY_SIZES: &[usize] = &[1_000, 10_000];
+
+const ARTISTS: &[&str] = &[
+    "The Midnight",
+    "Boards of Canada",
+    "Radiohead",
+    "Aphex Twin",
+    "Nujabes",
+    "Carpenter Brut",
+    "Massive Attack",
+    "Portishead",
+];
+
+const WORDS: &[&str] = &[
+    "summer", "rain", "love", "night", "drive", "ocean", "glass", "electric", "dream", "static",
+    "silver", "echo", "winter", "sunset", "neon",
+];
+
+/// deterministic fake library, spread out enough that queries hit a realistic fraction of it
+fn synthetic_library(n: usize) -> impl Iterator<Item = AudioFile> {
+    (0..n).map(|i| {
+        let artist = ARTISTS[i % ARTISTS.len()];
+        let album = format!(
+            "{} {}",
+            WORDS[i / 12 % WORDS.len()],
+            WORDS[i / 7 % WORDS.len()]
+        );
+        let title = format!(
+            "{} {} {}",
+            WORDS[i % WORDS.len()],
+            WORDS[i / 3 % WORDS.len()],
+            WORDS[i / 5 % WORDS.len()]
+        );
+        let track = (i % 12 + 1).to_string();
+
+        AudioFile::from_kv_and_path(
+            format!("/music/{artist}/{album}/{track} {title}.flac"),
+            [
+                ("artist", artist),
+                ("album", &*album),
+                ("title", &*title),
+                ("track", &*track),
+            ]
+            .into_iter(),
+            AudioFile::DEFAULT_MAX_TAG_LEN,
+        )
+    })
+}
+
+fn search_latency(c: &mut Criterion) {
+    let queries = [
+        ("single_term", "love"),
+        ("multi_term", "midnight summer drive"),
+        ("phrase", "\"electric dream\""),
+    ];
+
+    for &size in LIBRARY_SIZES {
+        let index = MusicIndex::from_audiofiles(synthetic_library(size)).unwrap();
+        let reader = index.reader().unwrap();
+        let qp = index.query_parser();
+
+        let mut group = c.benchmark_group(format!("search/{size}"));
+
+        for (name, query) in queries {
+            let q = qp.parse_query_lenient(query).0;
+
+            group.bench_with_input(BenchmarkId::from_parameter(name), &q, |b, q| {
+                let searcher = reader.searcher();
+
+                b.iter(|| {
+                    searcher
+                        .search(black_box(q), &TopDocs::with_limit(15))
+                        .unwrap()
+                })
+            });
+        }
+
+        group.finish();
+    }
+}
+
+/// recalling results with every stored field against only what a wide (every field) or a minimal
+/// (title and artist) display reads. the document store only hands out whole documents, so each
+/// is read whole either way, as a search does, and what is saved is turning the rest into tags
+fn recall(c: &mut Criterion) {
+    let index = MusicIndex::from_audiofiles(synthetic_library(10_000)).unwrap();
+    let reader = index.reader().unwrap();
+    let schema = index.schema();
+
+    let searcher = reader.searcher();
+    let q = index.query_parser().parse_query_lenient("love").0;
+    let addresses: Vec<DocAddress> = searcher
+        .search(&q, &TopDocs::with_limit(200))
+        .unwrap()
+        .into_iter()
+        .map(|(_, address)| address)
+        .collect();
+    let doc = |address: &DocAddress| -> TantivyDocument { searcher.doc(*address).unwrap() };
+
+    let fields = |shown: &[ShowField]| -> Vec<Field> {
+        shown.iter().flat_map(|f| f.fields(schema)).collect()
+    };
+    let wide = fields(ShowField::DEFAULT);
+    let minimal = fields(&[ShowField::Title, ShowField::Artist]);
+
+    let mut group = c.benchmark_group("recall");
+
+    group.bench_function("all", |b| {
+        b.iter(|| {
+            for address in &addresses {
+                black_box(AudioFile::tantivy_recall(schema, &doc(address)));
+            }
+        })
+    });
+
+    for (name, fields) in [("wide", &wide), ("minimal", &minimal)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for address in &addresses {
+                    black_box(AudioFile::tantivy_recall_only(
+                        schema,
+                        &doc(address),
+                        fields,
+                    ));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, search_latency, recall);
+criterion_main!(benches);
@@ -0,0 +1,190 @@
+//! the audio inside zip archives, for `--index-archives`. ffmpeg can not look inside them, so each
+//! file in one is extracted to be read, and indexed under a virtual path naming both,
+//! `zip://<archive>!<entry>`
+
+use std::{
+    fs,
+    io::{self, BufReader},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use zip::ZipArchive;
+
+use crate::AUDIO_EXT;
+
+/// what the path of every file inside an archive starts with
+pub(crate) const SCHEME: &str = "zip://";
+
+/// whether path is a zip archive, by its extension
+pub(crate) fn is_archive(path: &Utf8Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// the virtual path entry of archive is indexed under
+pub(crate) fn entry_path(archive: &Utf8Path, entry: &str) -> Utf8PathBuf {
+    format!("{SCHEME}{archive}!{entry}").into()
+}
+
+/// the archive and the entry in it a path made by [`entry_path`] stands for, none for the paths
+/// of files outside archives
+pub fn split_archive_path(path: &Utf8Path) -> Option<(&Utf8Path, &str)> {
+    let rest = path.as_str().strip_prefix(SCHEME)?;
+
+    // entries may have a `!` in them too, but the archive always ends in .zip
+    let end = rest.to_ascii_lowercase().find(".zip!")? + ".zip".len();
+
+    Some((Utf8Path::new(&rest[..end]), &rest[end + 1..]))
+}
+
+/// the zip archive at path, with only its central directory read so far
+fn open(archive: &Utf8Path) -> io::Result<ZipArchive<BufReader<fs::File>>> {
+    Ok(ZipArchive::new(BufReader::new(fs::File::open(archive)?))?)
+}
+
+/// the names of every file in archive with an audio extension, dirs left out. only the table of
+/// contents at the end of it is read, so this is cheap even for large archives
+pub fn audio_entries(archive: &Utf8Path) -> io::Result<Vec<String>> {
+    Ok(open(archive)?
+        .file_names()
+        .filter(|entry| {
+            Utf8Path::new(entry)
+                .extension()
+                .is_some_and(|ext| AUDIO_EXT.contains(ext))
+        })
+        .map(str::to_owned)
+        .collect())
+}
+
+/// writes entry of archive out to a file of the same name in dir, returning its path
+pub fn extract_entry(archive: &Utf8Path, entry: &str, dir: &Utf8Path) -> io::Result<Utf8PathBuf> {
+    let name = Utf8Path::new(entry)
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "entry has no file name"))?;
+
+    let mut zip = open(archive)?;
+    let mut contents = zip.by_name(entry)?;
+
+    let out = dir.join(name);
+
+    if let Err(e) = io::copy(&mut contents, &mut fs::File::create(&out)?) {
+        _ = fs::remove_file(&out);
+
+        return Err(e);
+    }
+
+    Ok(out)
+}
+
+/// a fresh dir under the temporary dir that is removed with everything in it on drop, entries
+/// are extracted into one each so two of the same name never overwrite each other
+pub struct ScratchDir(Utf8PathBuf);
+
+impl ScratchDir {
+    pub fn new() -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "musicsearch-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let dir = Utf8PathBuf::from_path_buf(dir).map_err(|dir| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("temporary directory {dir:?} is not utf-8"),
+            )
+        })?;
+
+        fs::create_dir(&dir)?;
+
+        Ok(Self(dir))
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.0
+    }
+
+    /// keeps the dir around after all, for files in it that are still being used once this is
+    /// gone, such as by a player it was opened with
+    pub fn keep(mut self) -> Utf8PathBuf {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        // kept dirs have had their path taken
+        if !self.0.as_str().is_empty() {
+            _ = fs::remove_dir_all(&self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    /// a zip in its own scratch dir holding each entry with its contents
+    fn zip_of(entries: &[(&str, &str)]) -> (ScratchDir, Utf8PathBuf) {
+        let dir = ScratchDir::new().unwrap();
+        let path = dir.path().join("album.zip");
+
+        let mut zip = ZipWriter::new(fs::File::create(&path).unwrap());
+        for (name, contents) in entries {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        (dir, path)
+    }
+
+    #[test]
+    fn entry_paths_split_back() {
+        let path = entry_path(Utf8Path::new("/music/a!b.ZIP"), "disc 1/01 hey!.flac");
+
+        assert_eq!(path, "zip:///music/a!b.ZIP!disc 1/01 hey!.flac");
+        assert_eq!(
+            split_archive_path(&path),
+            Some((Utf8Path::new("/music/a!b.ZIP"), "disc 1/01 hey!.flac"))
+        );
+        assert_eq!(split_archive_path(Utf8Path::new("/music/a.zip")), None);
+    }
+
+    #[test]
+    fn only_audio_entries_are_listed() {
+        let (_dir, path) = zip_of(&[
+            ("disc 1/01 one.flac", "one"),
+            ("cover.jpg", "jpg"),
+            ("02 [two].mp3", "two"),
+        ]);
+
+        assert_eq!(
+            audio_entries(&path).unwrap(),
+            ["disc 1/01 one.flac", "02 [two].mp3"]
+        );
+    }
+
+    #[test]
+    fn entries_extract_under_their_file_name() {
+        let (_dir, path) = zip_of(&[("disc 1/01 one.flac", "one"), ("02 [two].mp3", "two")]);
+        let out = ScratchDir::new().unwrap();
+
+        let one = extract_entry(&path, "disc 1/01 one.flac", out.path()).unwrap();
+        assert_eq!(one, out.path().join("01 one.flac"));
+        assert_eq!(fs::read_to_string(one).unwrap(), "one");
+
+        let two = extract_entry(&path, "02 [two].mp3", out.path()).unwrap();
+        assert_eq!(fs::read_to_string(two).unwrap(), "two");
+
+        assert!(extract_entry(&path, "03 three.mp3", out.path()).is_err());
+    }
+}
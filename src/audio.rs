@@ -0,0 +1,2700 @@
+use core::fmt;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fmt::Display,
+    fs, io,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tantivy::{
+    schema::{Field, FieldValue, OwnedValue, Value},
+    TantivyDocument,
+};
+use tracing::{trace, warn};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use walkdir::WalkDir;
+
+use crate::{archive, HardSchema, PlaylistEntry, Theme};
+
+/// composes text to NFC so equivalent strings written with or without combining characters are
+/// indexed (and searched) the same
+pub(crate) fn nfc(s: String) -> String {
+    if is_nfc(&s) {
+        s
+    } else {
+        s.nfc().collect()
+    }
+}
+
+pub const AUDIO_EXT: phf::Set<&'static str> = phf::phf_set! {
+    // trash
+    "mp3",
+
+    // open codecs/containers
+    "flac",
+    "opus",
+    "ape",
+    "ogg",
+    "mka",
+    "webm",
+
+    // apple stuff
+    "aac",
+    "alac",
+    "m4a",
+    "caf",
+
+    // windows stuff
+    "wma",
+    "wav",
+};
+
+pub struct AlbumKey {
+    pub ordered_paths: Vec<Utf8PathBuf>,
+
+    pub album_name: String,
+
+    /// unlike AudioFile which prefers artist over album_artist, we prefer album_artist here
+    pub artist_name: String,
+
+    pub year: Option<u32>,
+}
+
+impl AlbumKey {
+    /// what compilations are credited to by default, no matter what their individual tracks say
+    pub const VARIOUS_ARTISTS: &'static str = "Various Artists";
+
+    /// album artists that all mean a compilation, compared ignoring case. an album tagged with any
+    /// of these is credited to the various artists label instead, so one spelling per library (or
+    /// per tagger) does not split an album in two
+    pub const VARIOUS_ARTISTS_NAMES: &'static [&'static str] = &[
+        "various artists",
+        "various artist",
+        "various",
+        "va",
+        "v.a.",
+        "v/a",
+        "varios artistas",
+        "artistes divers",
+        "verschiedene interpreten",
+        "artisti vari",
+    ];
+
+    /// whether name is one of [`AlbumKey::VARIOUS_ARTISTS_NAMES`] or label
+    pub fn is_various_artists(name: &str, label: &str) -> bool {
+        name.eq_ignore_ascii_case(label)
+            || Self::VARIOUS_ARTISTS_NAMES
+                .iter()
+                .any(|n| n.eq_ignore_ascii_case(name))
+    }
+
+    /// groups files into albums by album name and album artist, files without an album are skipped.
+    /// compilations are credited to various_artists
+    pub fn group<'a>(
+        files: impl IntoIterator<Item = &'a AudioFile>,
+        various_artists: &'a str,
+    ) -> Vec<AlbumKey> {
+        let mut albums: BTreeMap<(&str, &str), Vec<&AudioFile>> = BTreeMap::new();
+
+        for file in files {
+            let Some(album) = &file.album else {
+                continue;
+            };
+
+            let artist = file.album_artist_name(various_artists).unwrap_or("");
+
+            albums.entry((album, artist)).or_default().push(file);
+        }
+
+        albums
+            .into_iter()
+            .map(|((album, artist), mut tracks)| {
+                tracks.sort_by(|a, b| (a.track, &a.file_path).cmp(&(b.track, &b.file_path)));
+
+                AlbumKey {
+                    year: tracks.iter().find_map(|t| t.year),
+                    ordered_paths: tracks.into_iter().map(|t| t.file_path.clone()).collect(),
+                    album_name: album.to_owned(),
+                    artist_name: artist.to_owned(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// name without a leading article, so "The Beatles" is "Beatles" for sorting and grouping.
+/// covers english and a few common european languages, a name that is only an article is kept
+pub fn without_leading_article(name: &str) -> &str {
+    const ARTICLES: &[&str] = &[
+        "the ", "a ", "an ", "die ", "der ", "das ", "le ", "la ", "les ", "l'", "el ", "los ",
+        "las ", "il ", "lo ", "gli ", "de ", "het ",
+    ];
+
+    ARTICLES
+        .iter()
+        .find_map(|article| {
+            let head = name.get(..article.len())?;
+            let rest = name[article.len()..].trim_start();
+
+            (head.eq_ignore_ascii_case(article) && !rest.is_empty()).then_some(rest)
+        })
+        .unwrap_or(name)
+}
+
+/// writes a key tag one way whichever notation it came in: camelot as `8A`, open key as `3m`, and
+/// notes as `C#m` or `Eb` for minor and major. anything else is kept as it was tagged
+fn normalize_key(key: &str) -> String {
+    let key = key.trim();
+
+    // camelot (1A-12B) and open key (1m-12d) are a number and a letter
+    let digits = key.len() - key.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if (1..=2).contains(&digits) && key.len() == digits + 1 {
+        let (n, letter) = key.split_at(digits);
+
+        match letter {
+            "A" | "a" | "B" | "b" => return format!("{n}{}", letter.to_ascii_uppercase()),
+            "M" | "m" | "D" | "d" => return format!("{n}{}", letter.to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+
+    let mut chars = key.chars();
+    let Some(note) = chars
+        .next()
+        .filter(|c| matches!(c.to_ascii_uppercase(), 'A'..='G'))
+    else {
+        return key.to_owned();
+    };
+
+    let rest = chars.as_str();
+    let (accidental, rest) = if let Some(rest) = rest.strip_prefix(['#', '♯']) {
+        ("#", rest)
+    } else if let Some(rest) = rest.strip_prefix(['b', '♭']) {
+        ("b", rest)
+    } else {
+        ("", rest)
+    };
+
+    let mode = match &*rest.trim().to_lowercase() {
+        "" | "maj" | "major" => "",
+        "m" | "min" | "minor" => "m",
+        _ => return key.to_owned(),
+    };
+
+    format!("{}{accidental}{mode}", note.to_ascii_uppercase())
+}
+
+/// value with every run of whitespace (tabs, newlines, doubled spaces) made a single space, so
+/// `Pink  Floyd` and `Pink Floyd` are the same artist. borrowed when there is nothing to collapse
+fn collapse_whitespace(value: &str) -> Cow<'_, str> {
+    let mut last_space = false;
+
+    let clean = value.chars().all(|c| {
+        let ok = !c.is_whitespace() || (c == ' ' && !last_space);
+        last_space = c.is_whitespace();
+        ok
+    });
+
+    if clean {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(value.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// value as it is placed, with its whitespace collapsed unless opts keep it
+fn tag_value<'a>(value: &'a str, opts: &ScanOptions) -> Cow<'a, str> {
+    if opts.normalize_whitespace {
+        collapse_whitespace(value)
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// a rating of whole stars out of 5, none for 0 which taggers write for unrated
+fn stars(rating: f64) -> Option<u8> {
+    let stars = rating.round();
+
+    (1.0..=5.0).contains(&stars).then_some(stars as u8)
+}
+
+/// the year a date starts with, as in `1998`, `1998-03-12` or `1998/03/12`
+fn leading_year(date: &str) -> Option<u32> {
+    let year = date.get(..4)?;
+
+    if !year.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    // `19980312` is a date, `199803` or `19981` is more likely not a year at all
+    match date.as_bytes().get(4) {
+        None | Some(b'-' | b'/' | b'.' | b' ') => year.parse().ok(),
+        Some(_) if date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) => year.parse().ok(),
+        Some(_) => None,
+    }
+}
+
+/// the field of [`AudioFile`] a tag is read into
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TagField {
+    AlbumArtist,
+    Artist,
+    Album,
+    Title,
+    Track,
+    Date,
+    /// a year of its own, which wins over the one date starts with
+    Year,
+    Work,
+    Movement,
+    MovementNumber,
+    Grouping,
+    /// when a reissue was first released, which only its year is kept of
+    OriginalYear,
+    Genre,
+    MbidTrack,
+    MbidAlbum,
+    Bpm,
+    MusicalKey,
+    /// a 0 to 1 rating, as fmps_rating is
+    Rating,
+    /// a rating out of 5 stars, 100 (as itunes and most vorbis taggers write) or 255 (as id3 POPM
+    /// is), whichever its value fits in
+    RatingScaled,
+    Compilation,
+    /// anything without a field of its own, kept in extras
+    Extra,
+}
+
+/// which field a tag key is read into, ignoring case. vorbis comments, id3 and mp4 each spell
+/// some keys their own way, and ffmpeg only renames some of them, so those are all listed
+pub fn classify_tag(key: &str) -> TagField {
+    match &*key.to_lowercase() {
+        "album_artist" | "albumartist" | "album artist" => TagField::AlbumArtist,
+        "artist" => TagField::Artist,
+        "album" => TagField::Album,
+        "title" => TagField::Title,
+        "track" | "tracknumber" => TagField::Track,
+        "date" => TagField::Date,
+        "year" | "tyer" => TagField::Year,
+        "work" | "©wrk" => TagField::Work,
+        "movementname" | "movement name" | "mvnm" | "©mvn" => TagField::Movement,
+        "movement" | "movementnumber" | "movement number" | "mvin" | "©mvi" => {
+            TagField::MovementNumber
+        }
+        "grouping" | "tit1" | "©grp" => TagField::Grouping,
+        "originaldate" | "originalyear" | "original year" | "tdor" | "tory" => {
+            TagField::OriginalYear
+        }
+        "genre" => TagField::Genre,
+        "musicbrainz_trackid" | "musicbrainz track id" => TagField::MbidTrack,
+        "musicbrainz_albumid" | "musicbrainz album id" => TagField::MbidAlbum,
+        "bpm" | "tbpm" | "tmpo" => TagField::Bpm,
+        "initialkey" | "key" | "tkey" => TagField::MusicalKey,
+        "fmps_rating" => TagField::Rating,
+        "rating" | "rate" | "popm" | "rating wmp" => TagField::RatingScaled,
+        "compilation" | "cpil" => TagField::Compilation,
+        _ => TagField::Extra,
+    }
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioFile {
+    /// displayed (but only index the filename)
+    pub file_path: Utf8PathBuf,
+
+    /// normally the same as artist, should be indexed but only displayed as fallback
+    pub album_artist: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    /// if a part of an album this is the track number within that album
+    pub track: Option<u64>,
+    pub date: Option<String>,
+    /// a track may be tagged with several genres, each one is kept (and indexed) separately
+    pub genre: Vec<String>,
+
+    /// the classical work a track is a movement of, as in "Symphony No. 5"
+    pub work: Option<String>,
+    /// name of the movement, which is often the title too
+    pub movement: Option<String>,
+    /// which movement of the work this is
+    pub movement_number: Option<u64>,
+    /// loose grouping of tracks a tagger or player set, such as a work or a set of sessions
+    pub grouping: Option<String>,
+
+    /// part of a various artists album, where per track artists should not split up the album
+    pub compilation: bool,
+
+    /// mtime of the file in seconds since the unix epoch, if the filesystem has one
+    pub modified: Option<u64>,
+
+    /// set on chapters, which are indexed as their own entries sharing the path of their file,
+    /// this is how many seconds into the file the chapter starts
+    pub start: Option<f64>,
+
+    /// may be parsed off of date if it exists, or via the explicit year key
+    pub year: Option<u32>,
+
+    /// year a reissue was first released, from originaldate or originalyear
+    pub original_year: Option<u32>,
+
+    /// of the best audio stream, if ffmpeg could tell
+    pub channels: Option<u64>,
+
+    /// length in whole seconds, of just the chapter for chapters
+    pub duration: Option<u64>,
+
+    /// for chapters, the length of the whole file they are in, which is what a chapter is kept
+    /// or left out by rather than its own. only known while reading the file, it is not stored
+    #[serde(skip)]
+    pub file_duration: Option<u64>,
+
+    /// how many chapters the file has embedded, none for files without any and for chapters
+    /// themselves. counted whether or not chapters are indexed
+    pub chapter_count: Option<u64>,
+
+    /// tempo, rounded to a whole beat
+    pub bpm: Option<u32>,
+    /// musical key, written one way whichever notation it was tagged in
+    pub musical_key: Option<String>,
+
+    /// stars out of 5, from 1 to 5, whatever scale the tag used
+    pub rating: Option<u8>,
+
+    /// shorter side in pixels of the embedded cover art, none without any or when its format does
+    /// not say
+    pub cover_res: Option<u64>,
+
+    /// musicbrainz recording and release ids, lowercased, these pin down a song far better than
+    /// its tags when the library was tagged against musicbrainz
+    pub mbid_track: Option<String>,
+    pub mbid_album: Option<String>,
+
+    /// lowercased hostname of the machine the file was indexed on, see [`ScanOptions::host`]
+    pub host: Option<String>,
+
+    /// name of the library (dir) the file was found in, see [`ScanOptions::library`]
+    pub library: Option<String>,
+
+    /// keys are first lowercased
+    pub extras: HashMap<String, String>,
+}
+
+impl AudioFile {
+    pub fn new(path: Utf8PathBuf) -> Self {
+        Self {
+            file_path: path,
+            ..Default::default()
+        }
+    }
+
+    /// longest a tag value is kept by default, anything longer is most likely something like a
+    /// base64 image pasted into a comment rather than text anyone would search for
+    pub const DEFAULT_MAX_TAG_LEN: usize = 4096;
+
+    /// values are trimmed and blank ones ignored, values longer than max_len bytes are cut short
+    /// and marked as such
+    pub fn place(
+        &mut self,
+        key: impl Into<String> + AsRef<str>,
+        value: impl Into<String>,
+        max_len: usize,
+    ) {
+        let k = key.as_ref().to_lowercase();
+        let mut value = nfc(value.into());
+
+        // taggers leave behind `title=` and the like, which says nothing about the file, so
+        // it is not stored and still counts as missing
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            return;
+        }
+        if trimmed.len() != value.len() {
+            value = trimmed.to_owned();
+        }
+
+        if value.len() > max_len {
+            warn!(
+                "truncating {k} of {} from {} bytes",
+                self.file_path,
+                value.len()
+            );
+
+            let mut end = max_len;
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+
+            value.truncate(end);
+            value.push_str("…[truncated]");
+        }
+
+        match classify_tag(&k) {
+            TagField::AlbumArtist => self.album_artist = Some(value),
+            TagField::Artist => self.artist = Some(value),
+            TagField::Album => self.album = Some(value),
+            TagField::Title => self.title = Some(value),
+            TagField::Track => {
+                let i = value.split_once('/').map_or(&*value, |(n, _total)| n);
+
+                if let Ok(n) = i.parse() {
+                    self.track = Some(n);
+                }
+            }
+            TagField::Date => self.date = Some(value),
+            TagField::Year => {
+                if let Some(year) = leading_year(&value) {
+                    self.year = Some(year);
+                }
+            }
+            TagField::Work => self.work = Some(value),
+            TagField::Movement => self.movement = Some(value),
+            TagField::MovementNumber => {
+                let n = value.split_once('/').map_or(&*value, |(n, _total)| n);
+
+                if let Ok(n) = n.trim().parse() {
+                    self.movement_number = Some(n);
+                }
+            }
+            TagField::Grouping => self.grouping = Some(value),
+            TagField::OriginalYear => {
+                // a full originaldate and a bare originalyear may both be tagged, either will do
+                if let Some(year) = leading_year(&value) {
+                    self.original_year = Some(year);
+                }
+            }
+            TagField::Genre => {
+                for genre in value.split([';', '/', '\0']).map(str::trim) {
+                    // the same genre may come from both the container and stream tags
+                    if !genre.is_empty() && !self.genre.iter().any(|g| g == genre) {
+                        self.genre.push(genre.to_owned());
+                    }
+                }
+            }
+            TagField::MbidTrack => self.mbid_track = Some(value.to_lowercase()),
+            TagField::MbidAlbum => self.mbid_album = Some(value.to_lowercase()),
+            TagField::Bpm => {
+                if let Ok(bpm) = value.parse::<f64>() {
+                    if bpm > 0.0 {
+                        self.bpm = Some(bpm.round() as u32);
+                    }
+                }
+            }
+            TagField::MusicalKey => self.musical_key = Some(normalize_key(&value)),
+            TagField::Rating => {
+                if let Ok(rating) = value.parse::<f64>() {
+                    self.rating = stars(rating * 5.0);
+                }
+            }
+            TagField::RatingScaled => {
+                if let Ok(rating) = value.parse::<f64>() {
+                    // a popm tag is always out of 255, where 64 is 2 stars and not 3 of 100
+                    let popm = k == "popm" && rating > 0.0;
+
+                    self.rating = match rating {
+                        ..=5.0 if !popm => stars(rating),
+                        ..=100.0 if !popm => stars(rating / 20.0),
+                        // POPM steps, which players write as 1, 64, 128, 196 and 255
+                        ..=255.0 => stars(match rating as u32 {
+                            0..32 => 1.0,
+                            32..96 => 2.0,
+                            96..160 => 3.0,
+                            160..224 => 4.0,
+                            _ => 5.0,
+                        }),
+                        _ => None,
+                    };
+                }
+            }
+            TagField::Compilation => {
+                self.compilation = matches!(&*value.to_lowercase(), "1" | "true" | "yes")
+            }
+            TagField::Extra => {
+                self.extras.insert(k, value);
+            }
+        }
+    }
+
+    /// the artist an album containing this file is credited to, unlike display this prefers
+    /// album_artist. compilations, by flag or by any common spelling of various artists, are
+    /// credited to various_artists
+    pub fn album_artist_name<'a>(&'a self, various_artists: &'a str) -> Option<&'a str> {
+        let artist = self.album_artist.as_deref().or(self.artist.as_deref());
+
+        if self.compilation
+            || artist.is_some_and(|a| AlbumKey::is_various_artists(a, various_artists))
+        {
+            return Some(various_artists);
+        }
+
+        artist
+    }
+
+    /// order for results that tie on score, so the same search always lists them the same way
+    pub fn cmp_tie_break(&self, other: &Self) -> Ordering {
+        (&self.file_path, self.track).cmp(&(&other.file_path, other.track))
+    }
+
+    /// order for results that tie on score that keeps folders together and each in track order,
+    /// with chapters after the file they are in. files without a track number come after those
+    /// with one in the same folder
+    pub fn cmp_folder_order(&self, other: &Self) -> Ordering {
+        fn key(f: &AudioFile) -> (Option<&Utf8Path>, bool, Option<u64>, &Utf8Path) {
+            (
+                f.file_path.parent(),
+                f.track.is_none(),
+                f.track,
+                &f.file_path,
+            )
+        }
+
+        let start = |f: &Self| f.start.unwrap_or(0.0);
+
+        key(self)
+            .cmp(&key(other))
+            .then_with(|| start(self).total_cmp(&start(other)))
+    }
+
+    /// names of the fields this file has no tag for, as `missing:<field>` finds them
+    pub fn missing_tags(&self) -> impl Iterator<Item = &'static str> {
+        [
+            (HardSchema::TITLE, self.title.is_none()),
+            (
+                HardSchema::ARTIST,
+                self.artist.is_none() && self.album_artist.is_none(),
+            ),
+            (HardSchema::ALBUM, self.album.is_none()),
+            (HardSchema::TRACK, self.track.is_none()),
+            (HardSchema::DATE, self.date.is_none()),
+            (HardSchema::GENRE, self.genre.is_empty()),
+        ]
+        .into_iter()
+        .filter_map(|(name, missing)| missing.then_some(name))
+    }
+
+    pub fn item_type(&self) -> &'static str {
+        match self.start {
+            Some(_) => "chapter",
+            None => "song",
+        }
+    }
+
+    pub fn from_kv_and_path<K, V>(
+        path: impl Into<Utf8PathBuf>,
+        kv: impl Iterator<Item = (K, V)>,
+        max_tag_len: usize,
+    ) -> Self
+    where
+        K: Into<String> + AsRef<str>,
+        V: Into<String>,
+    {
+        let mut this = Self::new(path.into());
+
+        for (k, v) in kv {
+            this.place(k, v, max_tag_len);
+        }
+
+        // only once every tag is placed, an explicit year key may come after the date
+        if this.year.is_none() {
+            this.year = this.date.as_deref().and_then(leading_year);
+        }
+
+        this
+    }
+
+    /// a file with exactly the fields set on the builder, without going through tag parsing,
+    /// for building fixtures in tests
+    #[cfg(test)]
+    pub fn builder(path: impl Into<Utf8PathBuf>) -> AudioFileBuilder {
+        AudioFileBuilder(Self::new(path.into()))
+    }
+
+    pub fn tantivy_store(&self, scm: &HardSchema) -> TantivyDocument {
+        let mut doc = TantivyDocument::new();
+
+        doc.add_text(scm.path, &self.file_path);
+        doc.add_text(scm.path_raw, &self.file_path);
+
+        // artist falls back to the album artist so `artist:` finds a track credited either way,
+        // which means a recalled file with only an album artist has it as its artist too
+        if let Some(artist) = self.artist.as_ref().or(self.album_artist.as_ref()) {
+            doc.add_text(scm.artist, artist);
+        }
+
+        if let Some(album_artist) = &self.album_artist {
+            doc.add_text(scm.album_artist, album_artist);
+        }
+
+        if let Some(album) = &self.album {
+            doc.add_text(scm.album, album);
+        }
+
+        if let Some(title) = &self.title {
+            doc.add_text(scm.title, title);
+        }
+
+        if let Some(track) = self.track {
+            doc.add_u64(scm.track, track);
+        }
+
+        if let Some(date) = &self.date {
+            doc.add_text(scm.date, date);
+        }
+
+        for genre in &self.genre {
+            doc.add_text(scm.genre, genre);
+        }
+
+        if let Some(work) = &self.work {
+            doc.add_text(scm.work, work);
+        }
+
+        if let Some(movement) = &self.movement {
+            doc.add_text(scm.movement, movement);
+        }
+
+        if let Some(n) = self.movement_number {
+            doc.add_u64(scm.movement_number, n);
+        }
+
+        if let Some(grouping) = &self.grouping {
+            doc.add_text(scm.grouping, grouping);
+        }
+
+        doc.add_bool(scm.compilation, self.compilation);
+
+        if let Some(modified) = self.modified {
+            doc.add_u64(scm.modified, modified);
+        }
+
+        // keys are kept so tags that have no dedicated field can still be searched for by name,
+        // promoted tags go in their own field instead and need no key
+        for (k, v) in &self.extras {
+            match scm.promoted.iter().find(|(name, _)| name == k) {
+                Some(&(_, field)) => doc.add_text(field, v),
+                None => {
+                    if let Some(extras) = scm.extras {
+                        doc.add_text(extras, format!("{k}:{v}"));
+                    }
+                }
+            }
+        }
+
+        if let Some(start) = self.start {
+            doc.add_f64(scm.start, start);
+        }
+
+        if let Some(year) = self.year {
+            doc.add_u64(scm.year, year.into());
+        }
+
+        if let Some(year) = self.original_year {
+            doc.add_u64(scm.original_year, year.into());
+        }
+
+        if let Some(channels) = self.channels {
+            doc.add_u64(scm.channels, channels);
+        }
+
+        if let Some(duration) = self.duration {
+            doc.add_u64(scm.duration, duration);
+        }
+
+        if let Some(count) = self.chapter_count {
+            doc.add_u64(scm.chapter_count, count);
+        }
+
+        if let Some(bpm) = self.bpm {
+            doc.add_u64(scm.bpm, bpm.into());
+        }
+
+        if let Some(key) = &self.musical_key {
+            doc.add_text(scm.musical_key, key);
+        }
+
+        if let Some(rating) = self.rating {
+            doc.add_u64(scm.rating, rating.into());
+        }
+
+        if let Some(res) = self.cover_res {
+            doc.add_u64(scm.cover_res, res);
+        }
+
+        if let Some(mbid) = &self.mbid_track {
+            doc.add_text(scm.mbid_track, mbid);
+        }
+
+        if let Some(mbid) = &self.mbid_album {
+            doc.add_text(scm.mbid_album, mbid);
+        }
+
+        if let Some(host) = &self.host {
+            doc.add_text(scm.host, host);
+        }
+
+        if let Some(library) = &self.library {
+            doc.add_text(scm.library, library);
+        }
+
+        for missing in self.missing_tags() {
+            doc.add_text(scm.missing, missing);
+        }
+
+        doc.add_text(scm.item_type, self.item_type());
+
+        let listed = [
+            (HardSchema::ARTIST, scm.artist),
+            (HardSchema::ALBUM_ARTIST, scm.album_artist),
+            (HardSchema::ALBUM, scm.album),
+            (HardSchema::TITLE, scm.title),
+            (HardSchema::GENRE, scm.genre),
+            (HardSchema::WORK, scm.work),
+        ];
+
+        let values: Vec<String> = listed
+            .iter()
+            .flat_map(|&(name, field)| {
+                doc.get_all(field)
+                    .filter_map(|v| v.as_str())
+                    .map(move |v| format!("{name}\0{v}"))
+            })
+            .collect();
+
+        for value in values {
+            doc.add_text(scm.values, value);
+        }
+
+        doc
+    }
+
+    fn store_fieldvalue(&mut self, scm: &HardSchema, fv: &FieldValue) {
+        let f = &fv.field;
+
+        fn must_string(v: &OwnedValue) -> String {
+            let OwnedValue::Str(s) = v else {
+                unreachable!("this field must be a string")
+            };
+
+            s.to_owned()
+        }
+
+        fn must_bool(v: &OwnedValue) -> bool {
+            let &OwnedValue::Bool(v) = v else {
+                unreachable!("this field must be a bool")
+            };
+
+            v
+        }
+
+        fn must_f64(v: &OwnedValue) -> f64 {
+            let &OwnedValue::F64(v) = v else {
+                unreachable!("this field must be a f64")
+            };
+
+            v
+        }
+
+        fn must_u64(v: &OwnedValue) -> u64 {
+            let &OwnedValue::U64(v) = v else {
+                unreachable!("this field must be a u64")
+            };
+
+            v
+        }
+
+        #[deny(unused_variables)]
+        let HardSchema {
+            path,
+            path_raw,
+            artist,
+            album_artist,
+            album,
+            title,
+            track,
+            date,
+            genre,
+            work,
+            movement,
+            movement_number,
+            grouping,
+            compilation,
+            modified,
+            start,
+            year,
+            original_year,
+            channels,
+            duration,
+            chapter_count,
+            bpm,
+            musical_key,
+            rating,
+            cover_res,
+            extras,
+            promoted,
+            mbid_track,
+            mbid_album,
+            host,
+            library,
+            missing,
+            item_type,
+            values,
+        } = scm;
+
+        // kept with the other tags without a field of their own, so they show up the same
+        if let Some((name, _)) = promoted.iter().find(|(_, field)| field == f) {
+            self.extras.insert(name.clone(), must_string(&fv.value));
+            return;
+        }
+
+        // path_raw, missing and values are never stored and item_type is derived from start
+        _ = (path_raw, missing, values, item_type);
+
+        match f {
+            _ if f == path => self.file_path = must_string(&fv.value).into(),
+            _ if f == artist => self.artist = Some(must_string(&fv.value)),
+            _ if f == album_artist => self.album_artist = Some(must_string(&fv.value)),
+            _ if f == album => self.album = Some(must_string(&fv.value)),
+            _ if f == title => self.title = Some(must_string(&fv.value)),
+            _ if f == track => self.track = Some(must_u64(&fv.value)),
+            _ if f == date => self.date = Some(must_string(&fv.value)),
+            _ if f == genre => self.genre.push(must_string(&fv.value)),
+            _ if f == work => self.work = Some(must_string(&fv.value)),
+            _ if f == movement => self.movement = Some(must_string(&fv.value)),
+            _ if f == movement_number => self.movement_number = Some(must_u64(&fv.value)),
+            _ if f == grouping => self.grouping = Some(must_string(&fv.value)),
+            _ if f == compilation => self.compilation = must_bool(&fv.value),
+            _ if f == modified => self.modified = Some(must_u64(&fv.value)),
+            _ if f == start => self.start = Some(must_f64(&fv.value)),
+            _ if f == year => self.year = u32::try_from(must_u64(&fv.value)).ok(),
+            _ if f == original_year => self.original_year = u32::try_from(must_u64(&fv.value)).ok(),
+            _ if f == channels => self.channels = Some(must_u64(&fv.value)),
+            _ if f == duration => self.duration = Some(must_u64(&fv.value)),
+            _ if f == chapter_count => self.chapter_count = Some(must_u64(&fv.value)),
+            _ if f == bpm => self.bpm = u32::try_from(must_u64(&fv.value)).ok(),
+            _ if f == musical_key => self.musical_key = Some(must_string(&fv.value)),
+            _ if f == rating => self.rating = u8::try_from(must_u64(&fv.value)).ok(),
+            _ if f == cover_res => self.cover_res = Some(must_u64(&fv.value)),
+            _ if f == mbid_track => self.mbid_track = Some(must_string(&fv.value)),
+            _ if f == mbid_album => self.mbid_album = Some(must_string(&fv.value)),
+            _ if f == host => self.host = Some(must_string(&fv.value)),
+            _ if f == library => self.library = Some(must_string(&fv.value)),
+            _ if Some(*f) == *extras => {
+                if let Some((k, v)) = must_string(&fv.value).split_once(':') {
+                    self.extras.insert(k.to_owned(), v.to_owned());
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    pub fn tantivy_recall(scm: &HardSchema, doc: &TantivyDocument) -> Self {
+        let mut s = Self::new(Utf8PathBuf::new());
+
+        for itm in doc.field_values() {
+            s.store_fieldvalue(scm, itm);
+        }
+
+        s
+    }
+
+    /// recalls only fields (and the path, which every file has), leaving this as if the file had
+    /// no tags for the rest. cheaper than [`AudioFile::tantivy_recall`] for results that are only
+    /// shown, in particular when extras are not among fields. doc itself is read whole all the
+    /// same, tantivy's document store has no way to read part of one, so the saving is only in
+    /// what is made of it
+    pub fn tantivy_recall_only(scm: &HardSchema, doc: &TantivyDocument, fields: &[Field]) -> Self {
+        let mut s = Self::new(Utf8PathBuf::new());
+
+        for itm in doc
+            .field_values()
+            .iter()
+            .filter(|fv| fv.field == scm.path || fields.contains(&fv.field))
+        {
+            s.store_fieldvalue(scm, itm);
+        }
+
+        s
+    }
+}
+
+/// a part of a file that can be shown in a result line
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShowField {
+    /// only the file name, the full path is always in the hyperlink
+    Path,
+    Title,
+    /// the work a movement is part of with its number, as in `Symphony No. 5, II`, and the
+    /// movement's name too where that is not already the title
+    Work,
+    /// where a chapter starts in its file
+    Start,
+    Artist,
+    Album,
+    Track,
+    Date,
+    Genre,
+    /// `[mono]`, `[5.1]` and so on, nothing for stereo which is what nearly everything is
+    Channels,
+    /// tempo and key together, as in `128 BPM · 8A`
+    Bpm,
+    /// `[12 chapters]` for files with chapters embedded, like audiobooks and mixes
+    Chapters,
+    /// stars, as in `★★★★☆`
+    Rating,
+}
+
+impl ShowField {
+    /// the fields of scm that showing this reads, for recalling only what gets shown
+    pub fn fields(self, scm: &HardSchema) -> Vec<Field> {
+        match self {
+            ShowField::Path => vec![scm.path],
+            ShowField::Title => vec![scm.title],
+            // the movement's name is left out where it is the title
+            ShowField::Work => vec![scm.work, scm.movement, scm.movement_number, scm.title],
+            ShowField::Start => vec![scm.start],
+            ShowField::Artist => vec![scm.artist, scm.album_artist],
+            ShowField::Album => vec![scm.album],
+            ShowField::Track => vec![scm.track],
+            ShowField::Date => vec![scm.date],
+            ShowField::Genre => vec![scm.genre],
+            ShowField::Channels => vec![scm.channels],
+            ShowField::Bpm => vec![scm.bpm, scm.musical_key],
+            ShowField::Chapters => vec![scm.chapter_count],
+            ShowField::Rating => vec![scm.rating],
+        }
+    }
+
+    pub const NAMES: &'static [&'static str] = &[
+        "path", "title", "work", "start", "artist", "album", "track", "date", "genre", "channels",
+        "bpm", "chapters", "rating",
+    ];
+
+    /// what `Display` for `AudioFile` shows, in this order
+    pub const DEFAULT: &'static [ShowField] = &[
+        ShowField::Path,
+        ShowField::Title,
+        ShowField::Work,
+        ShowField::Start,
+        ShowField::Artist,
+        ShowField::Album,
+        ShowField::Track,
+        ShowField::Date,
+        ShowField::Genre,
+        ShowField::Channels,
+        ShowField::Bpm,
+        ShowField::Chapters,
+        ShowField::Rating,
+    ];
+}
+
+impl FromStr for ShowField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let i = Self::NAMES
+            .iter()
+            .position(|&n| n == s)
+            .ok_or_else(|| format!("unknown field {s:?}"))?;
+
+        Ok(Self::DEFAULT[i])
+    }
+}
+
+/// n as roman numerals, the way movements are numbered, or as digits past where those stay
+/// readable
+fn roman_numeral(n: u64) -> String {
+    const NUMERALS: &[(u64, &str)] = &[
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    if n == 0 || n >= 90 {
+        return n.to_string();
+    }
+
+    let mut rest = n;
+    let mut out = String::new();
+
+    for &(value, numeral) in NUMERALS {
+        while rest >= value {
+            out.push_str(numeral);
+            rest -= value;
+        }
+    }
+
+    out
+}
+
+/// displays only some fields of a file, in the order given
+pub struct Shown<'a> {
+    file: &'a AudioFile,
+    fields: &'a [ShowField],
+    theme: &'a Theme,
+}
+
+impl Display for Shown<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (file, theme) = (self.file, self.theme);
+
+        // file name must exist to be a valid AudioFile
+        let fname = file.file_path.file_name().unwrap();
+
+        // the last field written, separators only go between fields and never before the first
+        let mut last = None;
+
+        fn sep(
+            f: &mut fmt::Formatter,
+            last: &mut Option<ShowField>,
+            field: ShowField,
+            color: &str,
+            sep: &str,
+        ) -> fmt::Result {
+            match last.replace(field) {
+                Some(_) => write!(f, "{color}{sep}"),
+                None => Ok(()),
+            }
+        }
+
+        for field in self.fields {
+            match field {
+                ShowField::Path => {
+                    sep(f, &mut last, *field, "", " ")?;
+                    write!(f, "{}{fname}", theme.path)?;
+                }
+                ShowField::Title => {
+                    if let Some(title) = &file.title {
+                        // reads as "file: title" but "artist - title"
+                        let s = match last {
+                            Some(ShowField::Path) => ": ",
+                            _ => " - ",
+                        };
+
+                        sep(f, &mut last, *field, theme.separator, s)?;
+                        write!(f, "{}{title}", theme.title)?;
+                    }
+                }
+                ShowField::Work => {
+                    if let Some(work) = &file.work {
+                        sep(f, &mut last, *field, "", " ")?;
+                        write!(f, "{}({work}", theme.album)?;
+
+                        if let Some(n) = file.movement_number {
+                            write!(f, ", {}", roman_numeral(n))?;
+                        }
+
+                        match &file.movement {
+                            Some(movement) if file.title.as_ref() != Some(movement) => {
+                                let s = if file.movement_number.is_some() {
+                                    ". "
+                                } else {
+                                    ", "
+                                };
+                                write!(f, "{s}{movement}")?;
+                            }
+                            _ => (),
+                        }
+
+                        write!(f, ")")?;
+                    }
+                }
+                ShowField::Start => {
+                    if let Some(start) = file.start {
+                        let secs = start as u64;
+                        let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+                        sep(f, &mut last, *field, "", " ")?;
+
+                        match h {
+                            0 => write!(f, "{}@{m}:{s:02}", theme.start)?,
+                            _ => write!(f, "{}@{h}:{m:02}:{s:02}", theme.start)?,
+                        }
+                    }
+                }
+                ShowField::Artist => {
+                    if let Some(artist) = file.artist.as_ref().or(file.album_artist.as_ref()) {
+                        sep(f, &mut last, *field, theme.separator, " - ")?;
+                        write!(f, "{}{artist}", theme.artist)?;
+                    }
+                }
+                ShowField::Album => {
+                    if let Some(album) = &file.album {
+                        sep(f, &mut last, *field, theme.separator, " - ")?;
+                        write!(f, "{}{album}", theme.album)?;
+                    }
+                }
+                ShowField::Track => {
+                    if let Some(track) = file.track {
+                        sep(f, &mut last, *field, "", " ")?;
+                        write!(f, "{}#{track}", theme.track)?;
+                    }
+                }
+                ShowField::Date => {
+                    if let Some(date) = &file.date {
+                        sep(f, &mut last, *field, "", " ")?;
+                        write!(f, "{}({date})", theme.date)?;
+                    }
+                }
+                ShowField::Genre => {
+                    if !file.genre.is_empty() {
+                        sep(f, &mut last, *field, "", " ")?;
+                        write!(f, "{}[{}]", theme.genre, file.genre.join(", "))?;
+                    }
+                }
+                ShowField::Channels => {
+                    let layout = match file.channels {
+                        None | Some(2) => continue,
+                        Some(1) => "mono".to_owned(),
+                        Some(6) => "5.1".to_owned(),
+                        Some(8) => "7.1".to_owned(),
+                        Some(n) => format!("{n}ch"),
+                    };
+
+                    sep(f, &mut last, *field, "", " ")?;
+                    write!(f, "{}[{layout}]", theme.track)?;
+                }
+                ShowField::Bpm => {
+                    let dj = match (file.bpm, &file.musical_key) {
+                        (None, None) => continue,
+                        (Some(bpm), None) => format!("{bpm} BPM"),
+                        (None, Some(key)) => key.clone(),
+                        (Some(bpm), Some(key)) => format!("{bpm} BPM · {key}"),
+                    };
+
+                    sep(f, &mut last, *field, "", " ")?;
+                    write!(f, "{}{dj}", theme.track)?;
+                }
+                ShowField::Chapters => {
+                    let count = match file.chapter_count {
+                        None | Some(0) => continue,
+                        Some(1) => "1 chapter".to_owned(),
+                        Some(n) => format!("{n} chapters"),
+                    };
+
+                    sep(f, &mut last, *field, "", " ")?;
+                    write!(f, "{}[{count}]", theme.track)?;
+                }
+                ShowField::Rating => {
+                    if let Some(rating) = file.rating {
+                        let rating = usize::from(rating.min(5));
+
+                        sep(f, &mut last, *field, "", " ")?;
+                        write!(
+                            f,
+                            "{}{}{}",
+                            theme.track,
+                            "★".repeat(rating),
+                            "☆".repeat(5 - rating)
+                        )?;
+                    }
+                }
+            }
+        }
+
+        // a result with nothing to show would be an invisible hyperlink
+        if last.is_none() {
+            write!(f, "{}{fname}", theme.path)?;
+        }
+
+        write!(f, "\x1b[0m")
+    }
+}
+
+/// builds an [`AudioFile`] a field at a time, see [`AudioFile::builder`]. values are kept as
+/// given, so unlike tags they are not trimmed, cut short or normalized
+#[cfg(test)]
+#[must_use]
+pub struct AudioFileBuilder(AudioFile);
+
+#[cfg(test)]
+impl AudioFileBuilder {
+    pub fn artist(mut self, artist: impl Into<String>) -> Self {
+        self.0.artist = Some(artist.into());
+        self
+    }
+
+    pub fn album_artist(mut self, album_artist: impl Into<String>) -> Self {
+        self.0.album_artist = Some(album_artist.into());
+        self
+    }
+
+    pub fn album(mut self, album: impl Into<String>) -> Self {
+        self.0.album = Some(album.into());
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.0.title = Some(title.into());
+        self
+    }
+
+    pub fn track(mut self, track: u64) -> Self {
+        self.0.track = Some(track);
+        self
+    }
+
+    pub fn date(mut self, date: impl Into<String>) -> Self {
+        self.0.date = Some(date.into());
+        self
+    }
+
+    /// the year is not taken from date, as it would be from tags
+    pub fn year(mut self, year: u32) -> Self {
+        self.0.year = Some(year);
+        self
+    }
+
+    /// adds a genre, can be called once for each of several
+    pub fn genre(mut self, genre: impl Into<String>) -> Self {
+        self.0.genre.push(genre.into());
+        self
+    }
+
+    pub fn compilation(mut self, compilation: bool) -> Self {
+        self.0.compilation = compilation;
+        self
+    }
+
+    pub fn modified(mut self, modified: u64) -> Self {
+        self.0.modified = Some(modified);
+        self
+    }
+
+    /// makes this a chapter starting this many seconds into its file
+    pub fn start(mut self, start: f64) -> Self {
+        self.0.start = Some(start);
+        self
+    }
+
+    pub fn channels(mut self, channels: u64) -> Self {
+        self.0.channels = Some(channels);
+        self
+    }
+
+    pub fn duration(mut self, duration: u64) -> Self {
+        self.0.duration = Some(duration);
+        self
+    }
+
+    pub fn bpm(mut self, bpm: u32) -> Self {
+        self.0.bpm = Some(bpm);
+        self
+    }
+
+    pub fn musical_key(mut self, key: impl Into<String>) -> Self {
+        self.0.musical_key = Some(key.into());
+        self
+    }
+
+    pub fn mbid_track(mut self, mbid: impl Into<String>) -> Self {
+        self.0.mbid_track = Some(mbid.into());
+        self
+    }
+
+    pub fn mbid_album(mut self, mbid: impl Into<String>) -> Self {
+        self.0.mbid_album = Some(mbid.into());
+        self
+    }
+
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.0.host = Some(host.into());
+        self
+    }
+
+    /// adds a tag without a field of its own, key is lowercased as it would be when read
+    pub fn extra(mut self, key: &str, value: impl Into<String>) -> Self {
+        self.0.extras.insert(key.to_lowercase(), value.into());
+        self
+    }
+
+    pub fn build(self) -> AudioFile {
+        self.0
+    }
+}
+
+impl AudioFile {
+    /// displays only fields, in their order, colored by theme
+    pub fn show<'a>(&'a self, fields: &'a [ShowField], theme: &'a Theme) -> Shown<'a> {
+        Shown {
+            file: self,
+            fields,
+            theme,
+        }
+    }
+}
+
+impl Display for AudioFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.show(ShowField::DEFAULT, &Theme::DEFAULT).fmt(f)
+    }
+}
+
+/// which side wins when a file's embedded tags and its json sidecar both have a key
+#[derive(Clone, Copy, Debug)]
+pub enum Sidecar {
+    /// the sidecar only fills in tags the file lacks
+    Tags,
+    /// the sidecar replaces whatever the file has embedded
+    Sidecar,
+}
+
+impl FromStr for Sidecar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tags" => Ok(Self::Tags),
+            "sidecar" => Ok(Self::Sidecar),
+            _ => Err(format!("unknown sidecar precedence {s:?}")),
+        }
+    }
+}
+
+impl Sidecar {
+    pub const NAMES: &'static [&'static str] = &["tags", "sidecar"];
+}
+
+/// which of a reissue's years is taken as its year
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum YearSource {
+    /// when this copy was released, from its date
+    #[default]
+    Release,
+    /// when the music was first released, from originaldate or originalyear, falling back to the
+    /// release year for files without either
+    Original,
+}
+
+impl FromStr for YearSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "release" => Ok(Self::Release),
+            "original" => Ok(Self::Original),
+            _ => Err(format!("unknown year source {s:?}")),
+        }
+    }
+}
+
+impl YearSource {
+    pub const NAMES: &'static [&'static str] = &["release", "original"];
+}
+
+/// the tags in the sidecar of path, `song.json` next to `song.wav`, which is a flat json object of
+/// tag names to values. numbers and bools are taken as their text, anything nested is skipped
+fn read_sidecar(path: &Utf8Path) -> Option<Vec<(String, String)>> {
+    let sidecar = path.with_extension("json");
+
+    let text = match fs::read_to_string(&sidecar) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("could not read sidecar {sidecar}: {e}");
+            return None;
+        }
+    };
+
+    let map: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&text)
+        .inspect_err(|e| warn!("ignoring sidecar {sidecar}, it is not a json object: {e}"))
+        .ok()?;
+
+    Some(
+        map.into_iter()
+            .filter_map(|(k, v)| match v {
+                serde_json::Value::String(s) => Some((k, s)),
+                serde_json::Value::Number(n) => Some((k, n.to_string())),
+                serde_json::Value::Bool(b) => Some((k, b.to_string())),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+/// what to read out of each file beyond its tags, and how to name it
+#[derive(Clone)]
+pub struct ScanOptions {
+    /// also index every embedded chapter as its own entry
+    pub chapters: bool,
+
+    /// paths are canonical, which resolves a symlinked root to wherever it points. with this set
+    /// files under the root are named through the root as it was passed instead, only links
+    /// below it are still resolved
+    pub keep_root_symlink: bool,
+
+    /// skip canonicalizing entirely and name files by the path they were walked to (made absolute),
+    /// which saves a syscall per file but lets one file show up under several paths
+    pub keep_walked_paths: bool,
+
+    /// also walk into entries whose names start with `.`, which are pruned otherwise
+    pub hidden: bool,
+
+    /// tag values are cut to this many bytes, see [`AudioFile::DEFAULT_MAX_TAG_LEN`]
+    pub max_tag_len: usize,
+
+    /// merge in the tags of a json file next to each one, see [`Sidecar`]
+    pub sidecars: Option<Sidecar>,
+
+    /// most files read at once, however many threads are reading. probes are mostly waiting on
+    /// the disk, and a spinning one seeks itself to a crawl with every thread reading a different
+    /// file
+    pub probe_concurrency: Option<NonZeroUsize>,
+
+    /// make every run of whitespace (tabs, newlines, doubled spaces) in a tag value a single space
+    pub normalize_whitespace: bool,
+
+    /// hostname every file read is marked with, so an index holding files from several machines
+    /// can tell whose paths are whose. lowercased, hostnames are not case sensitive
+    pub host: Option<String>,
+
+    /// which year files get where they were tagged with both a release and an original date
+    pub year_source: YearSource,
+
+    /// name every file read is marked with, for telling apart the dirs that make up an index
+    pub library: Option<String>,
+
+    /// also read the audio inside zip archives, see [`split_archive_path`](crate::split_archive_path)
+    pub archives: bool,
+
+    /// files already indexed or passed over, with the mtime they had when they were. those that
+    /// still have it are not read again, and give an error of kind
+    /// [`io::ErrorKind::AlreadyExists`] instead. an archive is listed under its own path
+    pub indexed: Option<Arc<HashMap<Utf8PathBuf, u64>>>,
+}
+
+/// counting semaphore capping how many files are read at once, unlimited without a cap
+struct Probes {
+    cap: Option<NonZeroUsize>,
+    running: Mutex<usize>,
+    freed: Condvar,
+}
+
+/// a slot taken from [`Probes`], given back on drop
+struct Probe<'a>(Option<&'a Probes>);
+
+impl Probes {
+    fn new(cap: Option<NonZeroUsize>) -> Self {
+        Self {
+            cap,
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// blocks until a file may be read
+    fn acquire(&self) -> Probe<'_> {
+        let Some(cap) = self.cap else {
+            return Probe(None);
+        };
+
+        let running = self.running.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut running = self
+            .freed
+            .wait_while(running, |running| *running >= cap.get())
+            .unwrap_or_else(PoisonError::into_inner);
+
+        *running += 1;
+
+        Probe(Some(self))
+    }
+}
+
+impl Drop for Probe<'_> {
+    fn drop(&mut self) {
+        if let Some(probes) = self.0 {
+            *probes
+                .running
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) -= 1;
+            probes.freed.notify_one();
+        }
+    }
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            chapters: false,
+            keep_root_symlink: false,
+            keep_walked_paths: false,
+            hidden: false,
+            max_tag_len: AudioFile::DEFAULT_MAX_TAG_LEN,
+            sidecars: None,
+            probe_concurrency: None,
+            normalize_whitespace: true,
+            host: None,
+            year_source: YearSource::Release,
+            library: None,
+            archives: false,
+            indexed: None,
+        }
+    }
+}
+
+/// every file under subdir read, one item per walked file holding what was read out of it (a song
+/// and its chapters, or all of an archive). there is an error for each file that could not be read
+/// (which says which file it was) and one of kind [`io::ErrorKind::Unsupported`] for each file
+/// skipped for not being audio
+pub fn recursive_find_audiofiles<'a>(
+    subdir: &Utf8Path,
+    opts: &'a ScanOptions,
+) -> impl ParallelIterator<Item = io::Result<Vec<AudioFile>>> + 'a {
+    // (what the root resolves to, the root as passed) to map canonical paths back through it
+    let root = opts
+        .keep_root_symlink
+        .then(|| {
+            let resolved = subdir.canonicalize_utf8().ok()?;
+            let passed = Utf8PathBuf::try_from(std::path::absolute(subdir).ok()?).ok()?;
+
+            Some((resolved, passed))
+        })
+        .flatten();
+
+    // without canonicalizing the walked paths are all there is, so they must at least be absolute
+    let subdir = if opts.keep_walked_paths {
+        std::path::absolute(subdir)
+            .ok()
+            .and_then(|p| Utf8PathBuf::try_from(p).ok())
+            .unwrap_or_else(|| subdir.to_owned())
+    } else {
+        subdir.to_owned()
+    };
+
+    let probes = Probes::new(opts.probe_concurrency);
+
+    walk(&subdir, opts)
+        .par_bridge()
+        .filter(|p| p.as_ref().map_or(true, |f| f.file_type().is_file()))
+        .map(move |res| {
+            let file = res.inspect_err(|e| warn!("skipping unreadable entry: {e}"))?;
+
+            let path = Utf8PathBuf::try_from(file.into_path()).map_err(|e| {
+                warn!("skipping non utf-8 path {:?}", e.as_path());
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("non utf-8 path {:?}", e.as_path()),
+                )
+            })?;
+
+            let is_archive = opts.archives && archive::is_archive(&path);
+
+            // before anything else about it is looked up, most of a library is covers and such
+            if !is_archive && !path.extension().is_some_and(|ext| AUDIO_EXT.contains(ext)) {
+                trace!("skipping {path}, not an audio file");
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{path} is not an audio file"),
+                ));
+            }
+
+            let unreadable = |e| Unreadable::error(&path, root.as_ref(), opts, e);
+
+            if is_unchanged(&path, root.as_ref(), opts).map_err(unreadable)? {
+                trace!("skipping {path}, unchanged since it was indexed or passed over");
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{path} is already indexed as it is"),
+                ));
+            }
+
+            if is_archive {
+                read_archive(&path, root.as_ref(), opts, &probes).map_err(unreadable)
+            } else {
+                read_audiofile(&path, root.as_ref(), opts, &probes).map_err(unreadable)
+            }
+        })
+}
+
+/// reads exactly the files a playlist lists, whatever their extension, one item per entry as
+/// [`recursive_find_audiofiles`] gives one per file. a title or artist the playlist gives (as from
+/// `#EXTINF`) is only used where the file has no tag of its own
+pub fn playlist_audiofiles(
+    entries: Vec<PlaylistEntry>,
+    opts: &ScanOptions,
+) -> impl ParallelIterator<Item = io::Result<Vec<AudioFile>>> + '_ {
+    let probes = Probes::new(opts.probe_concurrency);
+
+    entries.into_par_iter().map(move |entry| {
+        let mut files = read_audiofile(&entry.path, None, opts, &probes)
+            .map_err(|e| read_error(&entry.path, e))?;
+
+        for file in &mut files {
+            entry.fill_in(file);
+        }
+
+        Ok(files)
+    })
+}
+
+/// what a file [`recursive_find_audiofiles`] could not read gives as its error, saying which file
+/// it was by the path it would have been indexed under, along with the mtime it had so an index
+/// can pass over it until it changes
+#[derive(Debug)]
+pub struct Unreadable {
+    pub path: Utf8PathBuf,
+    pub modified: Option<u64>,
+    error: io::Error,
+}
+
+impl Unreadable {
+    /// the file e says could not be read, none for errors that are not about one
+    pub fn of(e: &io::Error) -> Option<&Self> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    /// logs that the walked file at path could not be read, and gives back an error of the same
+    /// kind holding an [`Unreadable`] for it
+    fn error(
+        path: &Utf8Path,
+        root: Option<&(Utf8PathBuf, Utf8PathBuf)>,
+        opts: &ScanOptions,
+        error: io::Error,
+    ) -> io::Error {
+        warn!("could not read {path}: {error}");
+
+        let unreadable = Self {
+            path: indexed_path(path, root, opts).unwrap_or_else(|_| path.to_owned()),
+            modified: modified_secs(path).ok().flatten(),
+            error,
+        };
+
+        io::Error::new(unreadable.error.kind(), unreadable)
+    }
+}
+
+impl Display for Unreadable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not read {}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for Unreadable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// logs that path could not be read, giving back the error with path in it so it still says which
+/// file it was once it is passed on
+fn read_error(path: &Utf8Path, e: io::Error) -> io::Error {
+    warn!("could not read {path}: {e}");
+
+    io::Error::new(e.kind(), format!("could not read {path}: {e}"))
+}
+
+/// every entry under subdir that gets looked at, with hidden ones pruned unless opts asks for them
+fn walk(
+    subdir: &Utf8Path,
+    opts: &ScanOptions,
+) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> {
+    let hidden = opts.hidden;
+
+    WalkDir::new(subdir)
+        .follow_links(true)
+        .into_iter()
+        // the root is walked even when hidden, it was asked for by name
+        .filter_entry(move |e| e.depth() == 0 || keep_entry(e.file_name(), hidden))
+}
+
+/// the path every file [`recursive_find_audiofiles`] finds under dir is indexed under, so what a
+/// saved index holds of dir is what has a path starting with it
+pub fn indexed_dir(dir: &Utf8Path, opts: &ScanOptions) -> io::Result<Utf8PathBuf> {
+    // a kept root symlink has files named through the root as passed, made absolute
+    if opts.keep_walked_paths || opts.keep_root_symlink {
+        Utf8PathBuf::try_from(std::path::absolute(dir)?).map_err(|e| e.into_io_error())
+    } else {
+        Ok(strip_verbatim(dir.canonicalize_utf8()?))
+    }
+}
+
+/// how many items [`recursive_find_audiofiles`] will give for subdir, one per file it walks to,
+/// counted without reading any of them so there is a total to show progress against
+pub fn count_files(subdir: &Utf8Path, opts: &ScanOptions) -> usize {
+    walk(subdir, opts)
+        .filter(|e| e.as_ref().map_or(true, |f| f.file_type().is_file()))
+        .count()
+}
+
+/// reads the one file at path the way it would be indexed, along with its chapters when opts
+/// asks for them, without looking at its extension
+pub fn read_file(path: &Utf8Path, opts: &ScanOptions) -> io::Result<Vec<AudioFile>> {
+    read_audiofile(path, None, opts, &Probes::new(None))
+}
+
+/// whether a walked entry is looked at, hidden ones only when asked for. AppleDouble files (`._`
+/// followed by the name of the file they belong to) are never audio even with an audio
+/// extension, so they are skipped regardless
+fn keep_entry(name: &OsStr, hidden: bool) -> bool {
+    let name = name.as_encoded_bytes();
+
+    match name {
+        [b'.', b'_', ..] => false,
+        [b'.', ..] => hidden,
+        _ => true,
+    }
+}
+
+/// canonical paths on windows are extended length paths (`\\?\C:\...`), which are shown and opened
+/// as the plain path they stand for instead. this is a no-op everywhere else
+fn strip_verbatim(path: Utf8PathBuf) -> Utf8PathBuf {
+    if !cfg!(windows) {
+        return path;
+    }
+
+    let Some(rest) = path.as_str().strip_prefix(r"\\?\") else {
+        return path;
+    };
+
+    match rest.strip_prefix(r"UNC\") {
+        Some(share) => format!(r"\\{share}").into(),
+        None => rest.into(),
+    }
+}
+
+/// the path a walked file is indexed under, canonical unless opts keeps walked paths and with a
+/// kept root symlink put back in place of what it resolves to
+fn indexed_path(
+    path: &Utf8Path,
+    root: Option<&(Utf8PathBuf, Utf8PathBuf)>,
+    opts: &ScanOptions,
+) -> io::Result<Utf8PathBuf> {
+    let mut path = if opts.keep_walked_paths {
+        path.to_owned()
+    } else {
+        strip_verbatim(path.canonicalize_utf8()?)
+    };
+
+    if let Some((resolved, passed)) = root {
+        if let Ok(rel) = path.strip_prefix(resolved) {
+            path = passed.join(rel);
+        }
+    }
+
+    Ok(path)
+}
+
+/// whether path is among opts' already indexed files with the mtime it has now
+fn is_unchanged(
+    path: &Utf8Path,
+    root: Option<&(Utf8PathBuf, Utf8PathBuf)>,
+    opts: &ScanOptions,
+) -> io::Result<bool> {
+    let Some(indexed) = &opts.indexed else {
+        return Ok(false);
+    };
+
+    let path = indexed_path(path, root, opts)?;
+
+    Ok(match indexed.get(&path) {
+        Some(&modified) => modified_secs(&path)? == Some(modified),
+        None => false,
+    })
+}
+
+/// the mtime of path in seconds since the unix epoch, none where the platform has no mtimes
+fn modified_secs(path: &Utf8Path) -> io::Result<Option<u64>> {
+    Ok(path
+        .metadata()?
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs()))
+}
+
+/// reads every audio file in the zip archive at path, extracting each to read it and indexing it
+/// under a path naming both (see [`split_archive_path`](crate::split_archive_path)) with the
+/// archive's mtime. a file in it that can not be read is skipped with a warning
+fn read_archive(
+    path: &Utf8Path,
+    root: Option<&(Utf8PathBuf, Utf8PathBuf)>,
+    opts: &ScanOptions,
+    probes: &Probes,
+) -> io::Result<Vec<AudioFile>> {
+    let archive = indexed_path(path, root, opts)?;
+    let modified = modified_secs(&archive)?;
+
+    // sidecars would be looked for next to the extracted copy, where there are none
+    let entry_opts = ScanOptions {
+        sidecars: None,
+        ..opts.clone()
+    };
+
+    let mut files = Vec::new();
+
+    for entry in archive::audio_entries(&archive)? {
+        let scratch = archive::ScratchDir::new()?;
+
+        let read = archive::extract_entry(&archive, &entry, scratch.path())
+            .and_then(|extracted| read_audiofile(&extracted, None, &entry_opts, probes));
+
+        match read {
+            Ok(read) => files.extend(read.into_iter().map(|mut file| {
+                file.file_path = archive::entry_path(&archive, &entry);
+                file.modified = modified;
+                file
+            })),
+            Err(e) => warn!("could not read {entry} in {archive}: {e}"),
+        }
+    }
+
+    Ok(files)
+}
+
+/// matroska and some mp4s keep their tags on the stream rather than the container, so both are
+/// read. later tags overwrite earlier ones when placed, so stream tags only fill in what the
+/// container lacks
+fn stream_then_container<'a>(
+    stream: impl Iterator<Item = (&'a str, &'a str)>,
+    container: impl Iterator<Item = (&'a str, &'a str)>,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    stream.chain(container)
+}
+
+/// reads the tags of a file already known to have an audio extension, along with its chapters
+/// when those are asked for
+fn read_audiofile(
+    path: &Utf8Path,
+    root: Option<&(Utf8PathBuf, Utf8PathBuf)>,
+    opts: &ScanOptions,
+    probes: &Probes,
+) -> io::Result<Vec<AudioFile>> {
+    let _probe = probes.acquire();
+
+    let path = indexed_path(path, root, opts)?;
+    let modified = modified_secs(&path)?;
+
+    let ffmpeg_meta = ffmpeg_next::format::input(&path)?;
+
+    let sidecar = opts.sidecars.and_then(|_| read_sidecar(&path));
+
+    let audio_stream = ffmpeg_meta.streams().best(ffmpeg_next::media::Type::Audio);
+    let stream_meta = audio_stream.as_ref().map(|s| s.metadata());
+
+    let kv = stream_then_container(
+        stream_meta.iter().flat_map(|m| m.iter()),
+        // metadata() is coming from a private Deref<Target = Context> type...
+        // TODO PR it to not be like this
+        ffmpeg_meta.metadata().iter(),
+    );
+
+    let sidecar_kv = sidecar
+        .iter()
+        .flatten()
+        .map(|(k, v)| (k.as_str(), v.as_str()));
+
+    // later values overwrite earlier ones, so whichever should win goes last
+    let kv: Box<dyn Iterator<Item = (&str, &str)>> = match opts.sidecars {
+        Some(Sidecar::Sidecar) => Box::new(kv.chain(sidecar_kv)),
+        _ => Box::new(sidecar_kv.chain(kv)),
+    };
+
+    let kv = kv.map(|(k, v)| (k, tag_value(v, opts)));
+
+    let channels = audio_stream.as_ref().and_then(|s| {
+        let context = ffmpeg_next::codec::context::Context::from_parameters(s.parameters()).ok()?;
+        let channels = context.decoder().audio().ok()?.channels();
+
+        // 0 is what ffmpeg has when the stream does not say
+        (channels > 0).then_some(u64::from(channels))
+    });
+
+    // the codec parameters have the size already, the picture itself is never decoded
+    let cover_res = ffmpeg_meta
+        .streams()
+        .find(|s| {
+            s.disposition()
+                .contains(ffmpeg_next::format::stream::Disposition::ATTACHED_PIC)
+        })
+        .and_then(|s| {
+            let context =
+                ffmpeg_next::codec::context::Context::from_parameters(s.parameters()).ok()?;
+            let video = context.decoder().video().ok()?;
+
+            let res = video.width().min(video.height());
+            (res > 0).then_some(u64::from(res))
+        });
+
+    let mut audio = AudioFile::from_kv_and_path(path, kv, opts.max_tag_len);
+    audio.modified = modified;
+    audio.channels = channels;
+    audio.cover_res = cover_res;
+    audio.host = opts.host.as_deref().map(str::to_lowercase);
+    audio.library.clone_from(&opts.library);
+
+    if opts.year_source == YearSource::Original {
+        audio.year = audio.original_year.or(audio.year);
+    }
+    // in AV_TIME_BASE units, and negative (AV_NOPTS_VALUE) when the container does not say
+    audio.duration = u64::try_from(ffmpeg_meta.duration())
+        .ok()
+        .map(|us| us / 1_000_000);
+
+    // cheap to count even when chapters are not indexed, ffmpeg has already read them
+    audio.chapter_count = match ffmpeg_meta.nb_chapters() {
+        0 => None,
+        n => Some(n.into()),
+    };
+
+    let mut chapters = Vec::new();
+
+    if opts.chapters {
+        chapters.extend(ffmpeg_meta.chapters().map(|chapter| {
+            chapter_entry(
+                &audio,
+                (chapter.start(), chapter.end()),
+                f64::from(chapter.time_base()),
+                chapter.metadata().get("title"),
+                opts,
+            )
+        }));
+    }
+
+    // the file goes before its chapters, as adding it is what deletes whatever was indexed under
+    // its path before
+    let mut files = vec![audio];
+    files.extend(chapters);
+
+    Ok(files)
+}
+
+/// the entry of a chapter of file spanning start to end, in units of time_base seconds. its title
+/// is placed like any other tag, and a chapter ending before it starts has no duration
+fn chapter_entry(
+    file: &AudioFile,
+    (start, end): (i64, i64),
+    time_base: f64,
+    title: Option<&str>,
+    opts: &ScanOptions,
+) -> AudioFile {
+    let mut entry = file.clone();
+
+    entry.chapter_count = None;
+    entry.file_duration = file.duration;
+
+    entry.start = Some(start as f64 * time_base);
+    entry.duration = end
+        .checked_sub(start)
+        .filter(|length| *length >= 0)
+        .map(|length| (length as f64 * time_base) as u64);
+
+    if let Some(title) = title {
+        entry.place(HardSchema::TITLE, tag_value(title, opts), opts.max_tag_len);
+    }
+
+    entry
+}
+
+/// the picture a file has embedded as its cover art, with the file extension of its format
+pub fn extract_cover(path: &Utf8Path) -> io::Result<Option<(Vec<u8>, &'static str)>> {
+    use ffmpeg_next::{codec::Id, format::stream::Disposition};
+
+    let mut input = ffmpeg_next::format::input(path)?;
+
+    let cover = input
+        .streams()
+        .find(|s| s.disposition().contains(Disposition::ATTACHED_PIC))
+        .map(|s| {
+            let ext = match s.parameters().id() {
+                Id::PNG => "png",
+                Id::BMP => "bmp",
+                Id::GIF => "gif",
+                Id::WEBP => "webp",
+                Id::TIFF => "tiff",
+                _ => "jpg",
+            };
+
+            (s.index(), ext)
+        });
+
+    let Some((index, ext)) = cover else {
+        return Ok(None);
+    };
+
+    // demuxers hand the attached picture out as the first packet of its stream, no decoding needed
+    Ok(input
+        .packets()
+        .find(|(stream, _)| stream.index() == index)
+        .and_then(|(_, packet)| packet.data().map(|data| (data.to_vec(), ext))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MusicIndex, Rank, SchemaOptions};
+
+    fn placed(tags: &[(&str, &str)]) -> AudioFile {
+        AudioFile::from_kv_and_path(
+            "/music/a.flac",
+            tags.iter().copied(),
+            AudioFile::DEFAULT_MAX_TAG_LEN,
+        )
+    }
+
+    /// the paths of every file that query finds among files
+    fn found(files: impl IntoIterator<Item = AudioFile>, query: &str) -> Vec<Utf8PathBuf> {
+        let index = MusicIndex::from_audiofiles(files).unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let (q, _) = crate::parse_query(&index.query_parser(), query);
+
+        index
+            .search(&searcher, &*q, query, 10, Rank::Bm25, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(_, f)| f.file_path)
+            .collect()
+    }
+
+    #[test]
+    fn blank_tags_count_as_missing() {
+        let blank = placed(&[("title", "   "), ("artist", "Someone")]);
+        assert!(blank.title.is_none());
+
+        let titled = AudioFile::from_kv_and_path(
+            "/music/b.flac",
+            [("title", "Something"), ("artist", "Someone")].into_iter(),
+            AudioFile::DEFAULT_MAX_TAG_LEN,
+        );
+
+        assert_eq!(found([blank, titled], "missing:title"), ["/music/a.flac"]);
+    }
+
+    #[test]
+    fn messy_whitespace_is_collapsed() {
+        let messy = "  Pink  Floyd\t-\n\r\nThe\u{a0} Wall ";
+
+        assert_eq!(collapse_whitespace(messy), "Pink Floyd - The Wall");
+        assert!(matches!(
+            collapse_whitespace("Pink Floyd"),
+            Cow::Borrowed("Pink Floyd")
+        ));
+
+        let opts = ScanOptions::default();
+        assert_eq!(tag_value(messy, &opts), "Pink Floyd - The Wall");
+
+        let kept = ScanOptions {
+            normalize_whitespace: false,
+            ..ScanOptions::default()
+        };
+        assert_eq!(tag_value(messy, &kept), messy);
+    }
+
+    #[test]
+    fn chapter_titles_are_placed_like_tags() {
+        let mut file = placed(&[("title", "The Whole Mix")]);
+        file.duration = Some(3600);
+        let opts = ScanOptions {
+            max_tag_len: 8,
+            ..ScanOptions::default()
+        };
+
+        let chapter = chapter_entry(
+            &file,
+            (0, 90_000),
+            0.001,
+            Some("  Cafe\u{301}\t\tIntro"),
+            &opts,
+        );
+        assert_eq!(chapter.title.as_deref(), Some("Caf\u{e9} In…[truncated]"));
+        assert_eq!(chapter.duration, Some(90));
+        assert_eq!(chapter.file_duration, Some(3600));
+
+        // a blank title leaves the file's, and a chapter ending before it starts has no length
+        let chapter = chapter_entry(&file, (5_000, 1_000), 0.001, Some("   "), &opts);
+        assert_eq!(chapter.title.as_deref(), Some("The Whole Mix"));
+        assert_eq!(chapter.start, Some(5.0));
+        assert_eq!(chapter.duration, None);
+
+        let chapter = chapter_entry(&file, (i64::MIN, i64::MAX), 1.0, None, &opts);
+        assert_eq!(chapter.duration, None);
+    }
+
+    #[test]
+    fn untitled_files_are_found_by_missing() {
+        let file = |path: &str, tags: &[(&str, &str)]| {
+            AudioFile::from_kv_and_path(path, tags.iter().copied(), AudioFile::DEFAULT_MAX_TAG_LEN)
+        };
+        let files = || {
+            [
+                file("/music/untitled.flac", &[("artist", "Boards of Canada")]),
+                file(
+                    "/music/titled.flac",
+                    &[("title", "Roygbiv"), ("artist", "Boards of Canada")],
+                ),
+                file("/music/bare.flac", &[("album_artist", "Autechre")]),
+            ]
+        };
+
+        let mut untitled = found(files(), "missing:title");
+        untitled.sort();
+        assert_eq!(untitled, ["/music/bare.flac", "/music/untitled.flac"]);
+
+        assert_eq!(
+            found(files(), "+missing:title +artist:boards"),
+            ["/music/untitled.flac"]
+        );
+
+        // an album artist stands in for the artist
+        assert!(found(files(), "missing:artist").is_empty());
+    }
+
+    #[test]
+    fn keys_are_written_one_way() {
+        for (tagged, key) in [
+            // camelot, open key
+            ("8a", "8A"),
+            ("12B", "12B"),
+            (" 3m ", "3m"),
+            ("10D", "10d"),
+            // notes
+            ("c#m", "C#m"),
+            ("C♯ minor", "C#m"),
+            ("eb", "Eb"),
+            ("E♭ major", "Eb"),
+            ("Abmin", "Abm"),
+            ("G", "G"),
+            ("f maj", "F"),
+            // kept as tagged
+            ("13A", "13A"),
+            ("123A", "123A"),
+            ("8X", "8X"),
+            ("H", "H"),
+            ("C dorian", "C dorian"),
+            ("", ""),
+        ] {
+            assert_eq!(normalize_key(tagged), key, "{tagged:?}");
+        }
+    }
+
+    #[test]
+    fn ratings_on_every_scale_become_stars() {
+        let rated = |key: &str, value: &str| placed(&[(key, value)]).rating;
+
+        for (key, value, stars) in [
+            // 0 to 5
+            ("rating", "0", None),
+            ("rating", "1", Some(1)),
+            ("rating", "3.4", Some(3)),
+            ("rating", "5", Some(5)),
+            // 0 to 100
+            ("rating", "20", Some(1)),
+            ("rating", "60", Some(3)),
+            ("rating", "100", Some(5)),
+            // POPM's 0 to 255, whatever the value
+            ("popm", "0", None),
+            ("popm", "1", Some(1)),
+            ("popm", "5", Some(1)),
+            ("popm", "64", Some(2)),
+            ("popm", "128", Some(3)),
+            ("popm", "196", Some(4)),
+            ("popm", "255", Some(5)),
+            // FMPS' 0 to 1
+            ("fmps_rating", "0", None),
+            ("fmps_rating", "0.6", Some(3)),
+            ("fmps_rating", "1.0", Some(5)),
+            // out of range or not a number
+            ("rating", "256", None),
+            ("rating", "-1", None),
+            ("rating", "five", None),
+        ] {
+            assert_eq!(rated(key, value), stars, "{key}={value}");
+        }
+    }
+
+    #[test]
+    fn leading_articles_are_dropped_once() {
+        for (name, sorted) in [
+            ("The Beatles", "Beatles"),
+            ("the  beatles", "beatles"),
+            ("THE WHO", "WHO"),
+            ("The The", "The"),
+            ("The", "The"),
+            ("The ", "The "),
+            ("Theatre of Tragedy", "Theatre of Tragedy"),
+            ("A Tribe Called Quest", "Tribe Called Quest"),
+            ("An Pierlé", "Pierlé"),
+            ("Die Ärzte", "Ärzte"),
+            ("L'Arc~en~Ciel", "Arc~en~Ciel"),
+            ("Los Lobos", "Lobos"),
+            ("Alan Parsons", "Alan Parsons"),
+            ("Éa", "Éa"),
+            ("", ""),
+        ] {
+            assert_eq!(without_leading_article(name), sorted, "{name:?}");
+        }
+    }
+
+    #[test]
+    fn long_tags_are_cut_on_a_char_boundary() {
+        let cut = |value: &str, max_len| {
+            AudioFile::from_kv_and_path("/a.flac", [("comment", value)].into_iter(), max_len)
+                .extras
+                .remove("comment")
+                .unwrap()
+        };
+
+        // é is 2 bytes and ヨ 3, a cut through either drops the whole char
+        for (value, max_len, kept) in [
+            ("abcdef", 6, "abcdef"),
+            ("abcdef", 3, "abc…[truncated]"),
+            ("aé", 2, "a…[truncated]"),
+            ("aé", 3, "aé"),
+            ("ヨヨ", 4, "ヨ…[truncated]"),
+            ("ヨヨ", 5, "ヨ…[truncated]"),
+            ("ヨヨ", 2, "…[truncated]"),
+        ] {
+            assert_eq!(cut(value, max_len), kept, "{value:?} cut to {max_len}");
+        }
+
+        // only what is left once trimmed counts
+        assert_eq!(cut("  abc  ", 3), "abc");
+    }
+
+    #[test]
+    fn classify_tag_aliases() {
+        for key in ["album_artist", "albumartist", "album artist"] {
+            assert_eq!(classify_tag(key), TagField::AlbumArtist, "{key}");
+        }
+
+        assert_eq!(classify_tag("date"), TagField::Date);
+        for key in ["year", "tyer"] {
+            assert_eq!(classify_tag(key), TagField::Year, "{key}");
+        }
+
+        for key in ["track", "tracknumber"] {
+            assert_eq!(classify_tag(key), TagField::Track, "{key}");
+        }
+
+        for key in ["originaldate", "originalyear", "tdor", "tory"] {
+            assert_eq!(classify_tag(key), TagField::OriginalYear, "{key}");
+        }
+
+        assert_eq!(classify_tag("compilation"), TagField::Compilation);
+        assert_eq!(classify_tag("cpil"), TagField::Compilation);
+    }
+
+    #[test]
+    fn classify_tag_ignores_case() {
+        assert_eq!(classify_tag("ALBUMARTIST"), TagField::AlbumArtist);
+        assert_eq!(classify_tag("Album Artist"), TagField::AlbumArtist);
+        assert_eq!(classify_tag("TITLE"), TagField::Title);
+        assert_eq!(classify_tag("MusicBrainz_TrackId"), TagField::MbidTrack);
+    }
+
+    #[test]
+    fn unknown_keys_go_to_extras() {
+        assert_eq!(classify_tag("lyrics"), TagField::Extra);
+        assert_eq!(classify_tag(""), TagField::Extra);
+
+        let file = placed(&[("LYRICS", "la la la"), ("Label", "Apple")]);
+
+        assert_eq!(
+            file.extras.get("lyrics").map(String::as_str),
+            Some("la la la")
+        );
+        assert_eq!(file.extras.get("label").map(String::as_str), Some("Apple"));
+    }
+
+    #[test]
+    fn track_of_total() {
+        assert_eq!(placed(&[("track", "3/12")]).track, Some(3));
+        assert_eq!(placed(&[("TRACKNUMBER", "7")]).track, Some(7));
+        assert_eq!(placed(&[("track", "side a")]).track, None);
+    }
+
+    #[test]
+    fn year_from_year_tag_or_date() {
+        assert_eq!(placed(&[("date", "1998-03-12")]).year, Some(1998));
+        assert_eq!(placed(&[("date", "1998/03/12")]).year, Some(1998));
+        assert_eq!(placed(&[("date", "19980312")]).year, Some(1998));
+        assert_eq!(placed(&[("date", "199803")]).year, None);
+
+        // an explicit year wins, whichever order the tags come in
+        assert_eq!(
+            placed(&[("year", "2001"), ("date", "1998")]).year,
+            Some(2001)
+        );
+        assert_eq!(
+            placed(&[("date", "1998"), ("tyer", "2001")]).year,
+            Some(2001)
+        );
+    }
+
+    #[test]
+    fn genres_split_on_separators() {
+        assert_eq!(
+            placed(&[("genre", "Rock; Alternative/Indie")]).genre,
+            ["Rock", "Alternative", "Indie"]
+        );
+        assert_eq!(placed(&[("genre", "Rock\0Pop")]).genre, ["Rock", "Pop"]);
+    }
+
+    #[test]
+    fn genres_are_trimmed_and_deduplicated() {
+        assert_eq!(
+            placed(&[("genre", " Rock ;  Pop ;; ")]).genre,
+            ["Rock", "Pop"]
+        );
+        assert_eq!(
+            placed(&[("genre", "Rock"), ("GENRE", "Rock;Jazz")]).genre,
+            ["Rock", "Jazz"]
+        );
+    }
+
+    #[test]
+    fn every_genre_is_searchable() {
+        let index = MusicIndex::from_audiofiles([
+            placed(&[("title", "a"), ("genre", "Rock; Alternative")]),
+            AudioFile::builder("/music/b.flac")
+                .title("b")
+                .genre("Jazz")
+                .build(),
+        ])
+        .unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let qp = index.query_parser();
+
+        for query in ["genre:rock", "genre:alternative"] {
+            let (q, _) = crate::parse_query(&qp, query);
+            let hits = index
+                .search(&searcher, &*q, query, 10, Rank::Bm25, &[])
+                .unwrap();
+
+            assert_eq!(hits.len(), 1, "{query}");
+            assert_eq!(hits[0].1.file_path, "/music/a.flac");
+        }
+    }
+
+    #[test]
+    fn decomposed_and_composed_tags_match_each_other() {
+        let decomposed = placed(&[("title", "Cafe\u{301} Society")]);
+        assert_eq!(decomposed.title.as_deref(), Some("Caf\u{e9} Society"));
+
+        let index = MusicIndex::from_audiofiles([
+            decomposed,
+            AudioFile::from_kv_and_path(
+                "/music/b.flac",
+                [("title", "R\u{e9}sum\u{e9}")].into_iter(),
+                AudioFile::DEFAULT_MAX_TAG_LEN,
+            ),
+        ])
+        .unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let qp = index.query_parser();
+
+        for (query, path) in [
+            ("caf\u{e9}", "/music/a.flac"),
+            ("re\u{301}sume\u{301}", "/music/b.flac"),
+        ] {
+            let (q, _) = crate::parse_query(&qp, query);
+            let hits = index
+                .search(&searcher, &*q, query, 10, Rank::Bm25, &[])
+                .unwrap();
+
+            assert_eq!(hits.len(), 1, "{query}");
+            assert_eq!(hits[0].1.file_path, path);
+        }
+    }
+
+    #[test]
+    fn compilation_flag() {
+        for value in ["1", "true", "YES"] {
+            assert!(placed(&[("compilation", value)]).compilation, "{value}");
+        }
+        assert!(placed(&[("cpil", "1")]).compilation);
+        assert!(!placed(&[("compilation", "0")]).compilation);
+        assert!(!placed(&[]).compilation);
+    }
+
+    #[test]
+    fn compilations_group_as_one_album() {
+        let files = [
+            AudioFile::builder("/va/02.flac")
+                .album("Hits")
+                .artist("B")
+                .track(2)
+                .compilation(true)
+                .build(),
+            AudioFile::builder("/va/01.flac")
+                .album("Hits")
+                .artist("A")
+                .track(1)
+                .compilation(true)
+                .build(),
+            // no flag, but credited to a spelling of various artists
+            AudioFile::builder("/va/03.flac")
+                .album("Hits")
+                .artist("C")
+                .album_artist("VA")
+                .track(3)
+                .build(),
+        ];
+
+        let albums = AlbumKey::group(&files, AlbumKey::VARIOUS_ARTISTS);
+
+        assert_eq!(albums.len(), 1);
+        assert_eq!(albums[0].artist_name, AlbumKey::VARIOUS_ARTISTS);
+        assert_eq!(
+            albums[0].ordered_paths,
+            ["/va/01.flac", "/va/02.flac", "/va/03.flac"]
+        );
+    }
+
+    #[test]
+    fn albums_of_different_artists_stay_apart() {
+        let files = [
+            AudioFile::builder("/a/01.flac")
+                .album("Greatest Hits")
+                .artist("A")
+                .build(),
+            AudioFile::builder("/b/01.flac")
+                .album("Greatest Hits")
+                .artist("B")
+                .build(),
+        ];
+
+        let albums = AlbumKey::group(&files, "Compilations");
+
+        assert_eq!(albums.len(), 2);
+        assert!(albums.iter().all(|a| a.artist_name != "Compilations"));
+    }
+
+    #[test]
+    fn stream_tags_do_not_clobber_container_tags() {
+        let stream = [("title", "Stream Title"), ("artist", "Stream Artist")];
+        let container = [("TITLE", "Container Title"), ("album", "Container Album")];
+
+        let file = AudioFile::from_kv_and_path(
+            "/music/a.mkv",
+            stream_then_container(stream.into_iter(), container.into_iter()),
+            AudioFile::DEFAULT_MAX_TAG_LEN,
+        );
+
+        assert_eq!(file.title.as_deref(), Some("Container Title"));
+        // tags only on the stream still fill in what the container lacks
+        assert_eq!(file.artist.as_deref(), Some("Stream Artist"));
+        assert_eq!(file.album.as_deref(), Some("Container Album"));
+    }
+
+    #[test]
+    fn store_and_recall_round_trips() {
+        let (_, scm) = HardSchema::schema(&SchemaOptions::default());
+
+        let file = AudioFile::builder("/music/Abbey Road/01 Come Together.flac")
+            .title("Come Together")
+            .artist("The Beatles")
+            .album("Abbey Road")
+            .track(1)
+            .date("1969-09-26")
+            .year(1969)
+            .genre("Rock")
+            .genre("Pop")
+            .bpm(82)
+            .extra("Label", "Apple")
+            .build();
+
+        let recalled = AudioFile::tantivy_recall(&scm, &file.tantivy_store(&scm));
+
+        assert_eq!(recalled.file_path, file.file_path);
+        assert_eq!(recalled.title.as_deref(), Some("Come Together"));
+        assert_eq!(recalled.artist.as_deref(), Some("The Beatles"));
+        assert_eq!(recalled.album.as_deref(), Some("Abbey Road"));
+        assert_eq!(recalled.track, Some(1));
+        assert_eq!(recalled.date.as_deref(), Some("1969-09-26"));
+        assert_eq!(recalled.year, Some(1969));
+        assert_eq!(recalled.genre, ["Rock", "Pop"]);
+        assert_eq!(recalled.bpm, Some(82));
+        assert_eq!(
+            recalled.extras.get("label").map(String::as_str),
+            Some("Apple")
+        );
+    }
+
+    #[test]
+    fn album_artists_are_stored_and_searched_alongside_artists() {
+        let (_, scm) = HardSchema::schema(&SchemaOptions::default());
+        let stored = |doc: &TantivyDocument, field| -> Vec<String> {
+            doc.get_all(field)
+                .filter_map(|v| v.as_str().map(str::to_owned))
+                .collect()
+        };
+
+        let both = AudioFile::builder("/music/both.flac")
+            .artist("Paul McCartney")
+            .album_artist("The Beatles")
+            .build();
+        let doc = both.tantivy_store(&scm);
+        assert_eq!(stored(&doc, scm.artist), ["Paul McCartney"]);
+        assert_eq!(stored(&doc, scm.album_artist), ["The Beatles"]);
+
+        let recalled = AudioFile::tantivy_recall(&scm, &doc);
+        assert_eq!(recalled.artist.as_deref(), Some("Paul McCartney"));
+        assert_eq!(recalled.album_artist.as_deref(), Some("The Beatles"));
+
+        // the album artist stands in for a missing artist, both when stored and recalled
+        let only = AudioFile::builder("/music/only.flac")
+            .album_artist("The Beatles")
+            .build();
+        let doc = only.tantivy_store(&scm);
+        assert_eq!(stored(&doc, scm.artist), ["The Beatles"]);
+        assert_eq!(stored(&doc, scm.album_artist), ["The Beatles"]);
+
+        let recalled = AudioFile::tantivy_recall(&scm, &doc);
+        assert_eq!(recalled.artist.as_deref(), Some("The Beatles"));
+        assert_eq!(recalled.album_artist.as_deref(), Some("The Beatles"));
+
+        let files = || [both.clone(), only.clone()];
+        let mut by_album_artist = found(files(), "album_artist:beatles");
+        by_album_artist.sort();
+        assert_eq!(by_album_artist, ["/music/both.flac", "/music/only.flac"]);
+        assert_eq!(found(files(), "artist:mccartney"), ["/music/both.flac"]);
+        assert_eq!(found(files(), "artist:beatles"), ["/music/only.flac"]);
+    }
+
+    #[test]
+    fn recall_only_leaves_out_unshown_fields() {
+        let (_, scm) = HardSchema::schema(&SchemaOptions::default());
+
+        let file = AudioFile::builder("/music/a.flac")
+            .title("A")
+            .artist("B")
+            .build();
+
+        let recalled =
+            AudioFile::tantivy_recall_only(&scm, &file.tantivy_store(&scm), &[scm.title]);
+
+        assert_eq!(recalled.file_path, file.file_path);
+        assert_eq!(recalled.title.as_deref(), Some("A"));
+        assert_eq!(recalled.artist, None);
+    }
+
+    #[test]
+    fn passed_over_files_are_not_read_again() {
+        let dir = crate::ScratchDir::new().unwrap();
+        fs::write(dir.path().join("cover.jpg"), "jpeg").unwrap();
+        fs::write(dir.path().join("broken.flac"), "not flac").unwrap();
+
+        let walked = |opts: &ScanOptions| {
+            let mut kinds: Vec<_> = recursive_find_audiofiles(dir.path(), opts)
+                .map(|res| res.unwrap_err())
+                .collect();
+            kinds.sort_by_key(|e| format!("{:?}", e.kind()));
+            kinds
+        };
+
+        let first = walked(&ScanOptions::default());
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[1].kind(), io::ErrorKind::Unsupported);
+
+        let broken = Unreadable::of(&first[0]).expect("the flac could not be read");
+        assert_eq!(broken.path.file_name(), Some("broken.flac"));
+
+        // as the next update would be told, the jpg is not even looked up
+        let opts = ScanOptions {
+            indexed: Some(Arc::new(HashMap::from([(
+                broken.path.clone(),
+                broken.modified.unwrap(),
+            )]))),
+            ..ScanOptions::default()
+        };
+
+        let second = walked(&opts);
+        assert_eq!(second[0].kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(second[1].kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_roots_name_files_as_asked() {
+        let dir = crate::ScratchDir::new().unwrap();
+        let real = dir.path().join("real");
+        let elsewhere = dir.path().join("elsewhere");
+        fs::create_dir_all(&real).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        fs::write(real.join("a.flac"), "not flac").unwrap();
+        fs::write(elsewhere.join("b.flac"), "not flac").unwrap();
+
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, real.join("inner")).unwrap();
+
+        let canonical = |p: &Utf8Path| p.canonicalize_utf8().unwrap();
+        let walked = |opts: &ScanOptions| {
+            let mut paths: Vec<Utf8PathBuf> = recursive_find_audiofiles(&link, opts)
+                .map(|res| Unreadable::of(&res.unwrap_err()).unwrap().path.clone())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        // canonical by default, so the root is named by where it points
+        let opts = ScanOptions::default();
+        assert_eq!(
+            walked(&opts),
+            [
+                canonical(&elsewhere).join("b.flac"),
+                canonical(&real).join("a.flac")
+            ]
+        );
+        assert_eq!(indexed_dir(&link, &opts).unwrap(), canonical(&real));
+
+        // named through the root as passed, links below it are still resolved
+        let opts = ScanOptions {
+            keep_root_symlink: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            walked(&opts),
+            [canonical(&elsewhere).join("b.flac"), link.join("a.flac")]
+        );
+        assert_eq!(indexed_dir(&link, &opts).unwrap(), link);
+
+        // named as walked, links below it too
+        let opts = ScanOptions {
+            keep_walked_paths: true,
+            ..ScanOptions::default()
+        };
+        assert_eq!(
+            walked(&opts),
+            [link.join("a.flac"), link.join("inner/b.flac")]
+        );
+    }
+
+    #[test]
+    fn every_walked_file_is_one_item_of_the_count() {
+        let dir = crate::ScratchDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("cover.jpg"), "jpeg").unwrap();
+        fs::write(dir.path().join("a.flac"), "not flac").unwrap();
+        fs::write(dir.path().join("sub/b.mp3"), "not mp3").unwrap();
+        fs::write(dir.path().join("songs.zip"), "not zip").unwrap();
+
+        let opts = ScanOptions {
+            archives: true,
+            ..ScanOptions::default()
+        };
+
+        // however many songs a file holds, or none at all, it is one item
+        let items = recursive_find_audiofiles(dir.path(), &opts).count();
+        assert_eq!(items, 4);
+        assert_eq!(count_files(dir.path(), &opts), items);
+    }
+}
@@ -0,0 +1,1596 @@
+use std::{cmp, collections::HashMap, fs, io, str::FromStr, time::SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tantivy::{
+    collector::{DocSetCollector, TopDocs},
+    directory::MmapDirectory,
+    postings::TermInfo,
+    query::{
+        AllQuery, MoreLikeThisQuery, Query, QueryParser, QueryParserError, RangeQuery, TermQuery,
+    },
+    schema::{Field, FieldType, IndexRecordOption, OwnedValue, Schema, Value},
+    tokenizer::{
+        NgramTokenizer, PreTokenizedStream, PreTokenizedString, RawTokenizer, SimpleTokenizer,
+        StopWordFilter, TextAnalyzer, Token, TokenStream, Tokenizer,
+    },
+    DocAddress, DocSet, Index, IndexReader, IndexSettings, IndexWriter, InvertedIndexReader,
+    Opstamp, Order, ReloadPolicy, Score, Searcher, SegmentReader, TantivyDocument, TantivyError,
+    Term, TERMINATED,
+};
+
+use crate::{archive, audio::nfc, without_leading_article, AudioFile, HardSchema, SchemaOptions};
+
+/// how hits are ordered
+#[derive(Clone, Copy, Default, Debug)]
+pub enum Rank {
+    /// tantivy's own scoring, documents with more of the query's ngrams rank higher
+    #[default]
+    Bm25,
+    /// hits whose title, artist or album contains the query rank first, the more of that tag the
+    /// query covers the higher, so "love" puts a song called "Love" above one called "Love Love
+    /// Love". bm25 breaks ties
+    Exact,
+}
+
+impl FromStr for Rank {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bm25" => Ok(Self::Bm25),
+            "exact" => Ok(Self::Exact),
+            _ => Err(format!("unknown ranking {s:?}")),
+        }
+    }
+}
+
+impl Rank {
+    pub const NAMES: &'static [&'static str] = &["bm25", "exact"];
+}
+
+/// a field search results can be sorted on
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortKey {
+    Path,
+    /// the artist as displayed, falling back to the album artist
+    Artist,
+    /// the artist without a leading article, so "The Beatles" sorts under B
+    ArtistSortName,
+    Album,
+    Title,
+    Track,
+    Date,
+    Modified,
+    Rating,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(Self::Path),
+            "artist" => Ok(Self::Artist),
+            "artist_sort" => Ok(Self::ArtistSortName),
+            "album" => Ok(Self::Album),
+            "title" => Ok(Self::Title),
+            "track" => Ok(Self::Track),
+            "date" => Ok(Self::Date),
+            "modified" => Ok(Self::Modified),
+            "rating" => Ok(Self::Rating),
+            _ => Err(format!("unknown sort key {s:?}")),
+        }
+    }
+}
+
+impl SortKey {
+    pub const NAMES: &'static [&'static str] = &[
+        "path",
+        "artist",
+        "artist_sort",
+        "album",
+        "title",
+        "track",
+        "date",
+        "modified",
+        "rating",
+    ];
+
+    /// the order a key sorts in when none is given, highest first for ratings and newest first
+    /// for mtimes, a to z for everything else
+    pub fn default_order(self) -> Order {
+        match self {
+            SortKey::Modified | SortKey::Rating => Order::Desc,
+            _ => Order::Asc,
+        }
+    }
+
+    /// the fields of scm comparing by this reads
+    pub fn fields(self, scm: &HardSchema) -> Vec<Field> {
+        match self {
+            SortKey::Path => vec![scm.path],
+            SortKey::Artist | SortKey::ArtistSortName => vec![scm.artist, scm.album_artist],
+            SortKey::Album => vec![scm.album],
+            SortKey::Title => vec![scm.title],
+            SortKey::Track => vec![scm.track],
+            SortKey::Date => vec![scm.date],
+            SortKey::Modified => vec![scm.modified],
+            SortKey::Rating => vec![scm.rating],
+        }
+    }
+
+    /// orders two files by this key alone, text ignoring case. files without a value always sort
+    /// after those with one, whichever way order goes
+    pub fn cmp(self, a: &AudioFile, b: &AudioFile, order: &Order) -> cmp::Ordering {
+        fn missing_last<T: Ord>(a: Option<T>, b: Option<T>, order: &Order) -> cmp::Ordering {
+            match (a, b) {
+                (Some(a), Some(b)) => match order {
+                    Order::Asc => a.cmp(&b),
+                    Order::Desc => b.cmp(&a),
+                },
+                (Some(_), None) => cmp::Ordering::Less,
+                (None, Some(_)) => cmp::Ordering::Greater,
+                (None, None) => cmp::Ordering::Equal,
+            }
+        }
+
+        let lower = |s: &Option<String>| s.as_deref().map(str::to_lowercase);
+        let artist = |f: &AudioFile| lower(&f.artist).or_else(|| lower(&f.album_artist));
+
+        match self {
+            SortKey::Path => missing_last(Some(&a.file_path), Some(&b.file_path), order),
+            SortKey::Artist => missing_last(artist(a), artist(b), order),
+            SortKey::ArtistSortName => {
+                let name = |f| artist(f).map(|a| without_leading_article(&a).to_owned());
+                missing_last(name(a), name(b), order)
+            }
+            SortKey::Album => missing_last(lower(&a.album), lower(&b.album), order),
+            SortKey::Title => missing_last(lower(&a.title), lower(&b.title), order),
+            SortKey::Track => missing_last(a.track, b.track, order),
+            SortKey::Date => missing_last(lower(&a.date), lower(&b.date), order),
+            SortKey::Modified => missing_last(a.modified, b.modified, order),
+            SortKey::Rating => missing_last(a.rating, b.rating, order),
+        }
+    }
+
+    /// orders two files by each key of sort in turn, equal where every key ties (or sort is
+    /// empty) so a stable sort keeps relevance order. every way results are sorted goes through
+    /// this, so a local search, the ui and a served one always agree
+    pub fn cmp_by(sort: &[(SortKey, Order)], a: &AudioFile, b: &AudioFile) -> cmp::Ordering {
+        sort.iter()
+            .map(|(key, order)| key.cmp(a, b, order))
+            .find(|o| o.is_ne())
+            .unwrap_or(cmp::Ordering::Equal)
+    }
+}
+
+/// how many times limit hits are reordered by exact ranking, anything containing the query all
+/// but always has enough of its ngrams to make it into that many
+const EXACT_CANDIDATES: usize = 10;
+
+/// the file in an index dir recording the options it was built with, tokenizer options among them
+/// change what is indexed without changing the schema
+const OPTIONS_FILE: &str = "musicsearch.json";
+
+/// files passed over when an index was last updated, for being excluded or not being readable, so
+/// they are not read again until they change. kept in the commit, so what it says always goes with
+/// the documents it was committed with
+#[derive(Default, Serialize, Deserialize)]
+pub struct Skipped {
+    /// the exclude flags they were passed over with, what those excluded has to be read again
+    /// once they change
+    pub excluded_by: String,
+    /// the mtime each had when it was passed over, files in archives under the archive's path
+    pub files: HashMap<Utf8PathBuf, u64>,
+}
+
+/// what [`MusicIndex::commit`] records in each commit
+#[derive(Default, Serialize, Deserialize)]
+struct Payload {
+    /// seconds since the unix epoch
+    committed: u64,
+    #[serde(default)]
+    skipped: Skipped,
+}
+
+/// the options the index in dir was built with, from its [`OPTIONS_FILE`]
+fn built_with(dir: &Utf8Path) -> tantivy::Result<SchemaOptions> {
+    let options = dir.join(OPTIONS_FILE);
+
+    fs::read(&options)
+        .and_then(|json| serde_json::from_slice(&json).map_err(io::Error::other))
+        .map_err(|e| {
+            TantivyError::SchemaError(format!(
+                "could not read the options it was built with from {options}: {e}"
+            ))
+        })
+}
+
+/// an error unless index has exactly the fields of scm
+fn check_fields(index: &Index, scm: &Schema) -> tantivy::Result<()> {
+    if index.schema() != *scm {
+        return Err(TantivyError::SchemaError(
+            "it was built by a version of musicsearch with other fields".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// a tantivy index of songs, with the tokenizer its schema expects already registered
+pub struct MusicIndex {
+    index: Index,
+    schema: HardSchema,
+}
+
+impl MusicIndex {
+    pub fn create_in_ram(opts: &SchemaOptions) -> Self {
+        let (scm, schema) = HardSchema::schema(opts);
+
+        let index = Index::create_in_ram(scm);
+
+        register_tokenizer(&index, opts);
+
+        Self { index, schema }
+    }
+
+    /// opens the index in dir, or creates one there (and dir with it) where there is none, along
+    /// with whether it was just created. an index built with other options than opts, or by a
+    /// version with other fields, is an error rather than being searched as if it were not
+    pub fn open_or_create_in_dir(
+        dir: &Utf8Path,
+        opts: &SchemaOptions,
+    ) -> tantivy::Result<(Self, bool)> {
+        let (scm, schema) = HardSchema::schema(opts);
+        let options = dir.join(OPTIONS_FILE);
+
+        fs::create_dir_all(dir)?;
+        let directory = MmapDirectory::open(dir)?;
+
+        let created = !Index::exists(&directory)?;
+
+        let index = if created {
+            let index = Index::create(directory, scm, IndexSettings::default())?;
+
+            let json =
+                serde_json::to_string_pretty(&opts.normalized()).map_err(io::Error::other)?;
+            fs::write(&options, json)?;
+
+            index
+        } else {
+            let index = Index::open(directory)?;
+
+            let differing = built_with(dir)?.differences(opts);
+
+            if !differing.is_empty() {
+                return Err(TantivyError::SchemaError(format!(
+                    "it was built with a different {}",
+                    differing.join(", ")
+                )));
+            }
+
+            check_fields(&index, &scm)?;
+
+            index
+        };
+
+        register_tokenizer(&index, opts);
+
+        Ok((Self { index, schema }, created))
+    }
+
+    /// opens the index already in dir along with the options it was built with, which it is read
+    /// with. an error where there is none, or where its fields are not what those options make
+    pub fn open_in_dir(dir: &Utf8Path) -> tantivy::Result<(Self, SchemaOptions)> {
+        let directory = MmapDirectory::open(dir)?;
+
+        if !Index::exists(&directory)? {
+            return Err(TantivyError::InvalidArgument(format!(
+                "there is no index in {dir}"
+            )));
+        }
+
+        let index = Index::open(directory)?;
+        let opts = built_with(dir)?;
+
+        let (scm, schema) = HardSchema::schema(&opts);
+        check_fields(&index, &scm)?;
+
+        register_tokenizer(&index, &opts);
+
+        Ok((Self { index, schema }, opts))
+    }
+
+    /// builds and commits an in ram index out of already parsed files, this skips ffmpeg entirely
+    /// and is intended for benches and tests
+    pub fn from_audiofiles(files: impl IntoIterator<Item = AudioFile>) -> tantivy::Result<Self> {
+        let this = Self::create_in_ram(&SchemaOptions::default());
+
+        let mut writer = this.writer()?;
+
+        for file in files {
+            writer.add_document(file.tantivy_store(&this.schema))?;
+        }
+
+        this.commit(&mut writer)?;
+
+        Ok(this)
+    }
+
+    pub fn writer(&self) -> tantivy::Result<IndexWriter> {
+        self.index.writer(20_000_000)
+    }
+
+    /// commits writer, recording when in the commit itself so [`MusicIndex::committed`] still
+    /// knows once the index is opened again much later. the files the last commit recorded as
+    /// skipped are kept as they are
+    pub fn commit(&self, writer: &mut IndexWriter) -> tantivy::Result<Opstamp> {
+        let skipped = self.skipped()?;
+
+        self.commit_skipped(writer, skipped)
+    }
+
+    /// commits writer like [`MusicIndex::commit`], recording skipped as the files it was updated
+    /// from but does not hold
+    pub fn commit_skipped(
+        &self,
+        writer: &mut IndexWriter,
+        skipped: Skipped,
+    ) -> tantivy::Result<Opstamp> {
+        let payload = Payload {
+            committed: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            skipped,
+        };
+
+        let mut commit = writer.prepare_commit()?;
+        commit.set_payload(&serde_json::to_string(&payload).map_err(io::Error::other)?);
+        commit.commit()
+    }
+
+    /// what the last commit recorded, none if it was not made by [`MusicIndex::commit`]
+    fn payload(&self) -> tantivy::Result<Option<Payload>> {
+        Ok(self
+            .index
+            .load_metas()?
+            .payload
+            .and_then(|payload| serde_json::from_str(&payload).ok()))
+    }
+
+    /// when the index was last committed in seconds since the unix epoch, none if that commit was
+    /// not made by [`MusicIndex::commit`]
+    pub fn committed(&self) -> tantivy::Result<Option<u64>> {
+        Ok(self.payload()?.map(|payload| payload.committed))
+    }
+
+    /// the files the last commit recorded as skipped, see [`Skipped`]
+    pub fn skipped(&self) -> tantivy::Result<Skipped> {
+        Ok(self.payload()?.unwrap_or_default().skipped)
+    }
+
+    /// a reader that picks up every commit shortly after it is made, including those another
+    /// process (`--serve-index`) makes to the same saved index
+    pub fn reader(&self) -> tantivy::Result<IndexReader> {
+        self.index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::OnCommitWithDelay)
+            .try_into()
+    }
+
+    /// a query parser searching over every indexed field by default, except missing, whose values
+    /// are field names that would match any search for them, and host and lib, which every file
+    /// from a machine or dir shares
+    pub fn query_parser(&self) -> QueryParser {
+        let scm = self.index.schema();
+
+        let fields = self
+            .schema
+            .all()
+            .into_iter()
+            .filter(|&f| {
+                ![
+                    self.schema.missing,
+                    self.schema.path_raw,
+                    self.schema.values,
+                    self.schema.host,
+                    self.schema.library,
+                ]
+                .contains(&f)
+                    && scm.get_field_entry(f).is_indexed()
+            })
+            .collect();
+
+        QueryParser::for_index(&self.index, fields)
+    }
+
+    /// a parser that searches only the named fields when a query does not give one, unknown names
+    /// are left out
+    pub fn query_parser_for(&self, names: &[impl AsRef<str>]) -> QueryParser {
+        let scm = self.index.schema();
+
+        let fields = names
+            .iter()
+            .filter_map(|name| scm.get_field(name.as_ref()).ok())
+            .collect();
+
+        QueryParser::for_index(&self.index, fields)
+    }
+
+    /// the top limit hits for query best first, ordered the way rank asks for. text is the query
+    /// as it was typed, which exact ranking compares tags against
+    pub fn top_docs(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        text: &str,
+        limit: usize,
+        rank: Rank,
+    ) -> tantivy::Result<Vec<(Score, DocAddress)>> {
+        // TopDocs panics on 0 and allocates for as many hits as asked for, more than there are
+        // documents can never be found anyhow
+        let limit = limit.min(searcher.num_docs() as usize);
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        match rank {
+            Rank::Bm25 => searcher.search(query, &TopDocs::with_limit(limit)),
+            Rank::Exact => {
+                // quotes are dropped so phrases and --literal queries still compare as text
+                let text = nfc(text.replace(['"', '\\'], "").trim().to_lowercase());
+
+                // a short tag that is all query can score below a long one repeating it, so
+                // reorder a wider pool of candidates by how much of a tag the query covers
+                let candidates = limit
+                    .saturating_mul(EXACT_CANDIDATES)
+                    .min(searcher.num_docs() as usize);
+
+                let mut hits = searcher
+                    .search(query, &TopDocs::with_limit(candidates))?
+                    .into_iter()
+                    .map(|(score, address)| {
+                        let doc: TantivyDocument = searcher.doc(address)?;
+
+                        Ok((self.coverage(&doc, &text), score, address))
+                    })
+                    .collect::<tantivy::Result<Vec<_>>>()?;
+
+                // stable, so hits covering as much keep their bm25 order
+                hits.sort_by(|(a, ..), (b, ..)| b.total_cmp(a));
+
+                Ok(hits
+                    .into_iter()
+                    .take(limit)
+                    .map(|(_, score, address)| (score, address))
+                    .collect())
+            }
+        }
+    }
+
+    /// the largest fraction of a title, artist or album that text makes up, 0 if none contain it
+    fn coverage(&self, doc: &TantivyDocument, text: &str) -> f32 {
+        if text.is_empty() {
+            return 0.0;
+        }
+
+        [self.schema.title, self.schema.artist, self.schema.album]
+            .into_iter()
+            .flat_map(|field| doc.get_all(field))
+            .filter_map(|v| v.as_str())
+            .map(str::to_lowercase)
+            .filter(|v| v.contains(text))
+            .map(|v| text.chars().count() as f32 / v.chars().count() as f32)
+            .fold(0.0, f32::max)
+    }
+
+    /// the top limit hits for query with every result recalled. they are best first with ties in
+    /// a stable order, unless sort is given, in which case the same hits are instead ordered by
+    /// each key in turn with relevance breaking whatever ties remain
+    pub fn search(
+        &self,
+        searcher: &Searcher,
+        query: &dyn Query,
+        text: &str,
+        limit: usize,
+        rank: Rank,
+        sort: &[(SortKey, Order)],
+    ) -> tantivy::Result<Vec<(Score, AudioFile)>> {
+        let mut hits = self
+            .top_docs(searcher, query, text, limit, rank)?
+            .into_iter()
+            .map(|(score, address)| {
+                let doc = searcher.doc(address)?;
+
+                Ok((score, AudioFile::tantivy_recall(&self.schema, &doc)))
+            })
+            .collect::<tantivy::Result<Vec<_>>>()?;
+
+        order_ties(&mut hits, |f| f, false);
+
+        // stable, so relevance order survives wherever every key ties
+        hits.sort_by(|(_, a), (_, b)| SortKey::cmp_by(sort, a, b));
+
+        Ok(hits)
+    }
+
+    /// the limit most recently modified files, newest first
+    pub fn recent(&self, searcher: &Searcher, limit: usize) -> tantivy::Result<Vec<AudioFile>> {
+        // as in top_docs, TopDocs panics on 0
+        let limit = limit.min(searcher.num_docs() as usize);
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+
+        let collector =
+            TopDocs::with_limit(limit).order_by_u64_field(HardSchema::MODIFIED, Order::Desc);
+
+        searcher
+            .search(&AllQuery, &collector)?
+            .into_iter()
+            .map(|(_, address)| {
+                Ok(AudioFile::tantivy_recall(
+                    &self.schema,
+                    &searcher.doc(address)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// calls each with every distinct value of the field called name, one of
+    /// [`HardSchema::VALUE_FIELDS`], in byte order until it returns false. values are read off the
+    /// term dictionary of [`HardSchema::VALUES`] a segment at a time, so this never holds more than
+    /// a term per segment, however many documents there are
+    pub fn for_each_value(
+        &self,
+        searcher: &Searcher,
+        name: &str,
+        mut each: impl FnMut(&str) -> bool,
+    ) -> tantivy::Result<()> {
+        if !HardSchema::VALUE_FIELDS.contains(&name) {
+            return Err(TantivyError::InvalidArgument(format!(
+                "the values of {name} are not listed"
+            )));
+        }
+
+        let prefix = format!("{name}\0");
+        let end = format!("{name}\u{1}");
+
+        let segments = searcher
+            .segment_readers()
+            .iter()
+            .map(|segment| Ok((segment, segment.inverted_index(self.schema.values)?)))
+            .collect::<tantivy::Result<Vec<_>>>()?;
+
+        let mut streams = segments
+            .iter()
+            .map(|(_, terms)| terms.terms().range().ge(&prefix).lt(&end).into_stream())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // streams start before their first term, the ones run out of are left behind
+        let mut live: Vec<bool> = streams.iter_mut().map(|s| s.advance()).collect();
+
+        // each segment's terms are sorted, so the smallest any is at comes next overall
+        while let Some(term) = streams
+            .iter()
+            .zip(&live)
+            .filter(|(_, &live)| live)
+            .map(|(s, _)| s.key())
+            .min()
+            .map(<[u8]>::to_vec)
+        {
+            let mut alive = false;
+
+            for (i, stream) in streams.iter_mut().enumerate() {
+                if !live[i] || stream.key() != term {
+                    continue;
+                }
+
+                // deleted documents keep their terms until their segment is merged
+                let (segment, terms) = &segments[i];
+                alive = alive || has_alive_doc(segment, terms, stream.value())?;
+
+                live[i] = stream.advance();
+            }
+
+            if alive && !each(&String::from_utf8_lossy(&term[prefix.len()..])) {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// the mtime every indexed path had when it was read, for telling which files changed since.
+    /// none for paths read where mtimes are not available
+    pub fn modified_times(
+        &self,
+        searcher: &Searcher,
+    ) -> tantivy::Result<HashMap<Utf8PathBuf, Option<u64>>> {
+        let mut times = HashMap::new();
+
+        for address in searcher.search(&AllQuery, &DocSetCollector)? {
+            let doc = searcher.doc(address)?;
+            let file = AudioFile::tantivy_recall_only(&self.schema, &doc, &[self.schema.modified]);
+
+            times.insert(file.file_path, file.modified);
+        }
+
+        Ok(times)
+    }
+
+    /// queues deleting the file (and any chapters of it) indexed under exactly path, documents
+    /// added with writer after this are kept
+    pub fn delete_path(&self, writer: &IndexWriter, path: &str) {
+        writer.delete_term(Term::from_field_text(self.schema.path_raw, path));
+    }
+
+    /// queues deleting every file indexed under dir and every file in an archive under it, dir
+    /// being a path as indexed like [`MusicIndex::lookup`] takes. documents added with writer
+    /// after this are kept
+    pub fn delete_under(&self, writer: &IndexWriter, dir: &Utf8Path) -> tantivy::Result<()> {
+        let sep = std::path::MAIN_SEPARATOR;
+        // the character right after the separator, no path under dir sorts at or past dir with
+        // it in place of the separator
+        let after = char::from(sep as u8 + 1);
+
+        let dir = dir.as_str().trim_end_matches(sep);
+
+        for dir in [dir.to_owned(), format!("{}{dir}", archive::SCHEME)] {
+            writer.delete_query(Box::new(RangeQuery::new_str(
+                HardSchema::PATH_RAW.to_owned(),
+                &*format!("{dir}{sep}")..&*format!("{dir}{after}"),
+            )))?;
+        }
+
+        Ok(())
+    }
+
+    /// adds every file in other to this index without committing, replacing the files indexed
+    /// under the same paths with it, returning how many entries were added. other has to have the
+    /// same fields and so be built with the same options, or what it indexed would not be what
+    /// this index searches for
+    pub fn merge_from(&self, writer: &IndexWriter, other: &MusicIndex) -> tantivy::Result<usize> {
+        if other.index.schema() != self.index.schema() {
+            return Err(TantivyError::SchemaError(
+                "it was built with other fields".into(),
+            ));
+        }
+
+        let searcher = other.reader()?.searcher();
+
+        // paths go first so a file and its chapters are all replaced before any are added
+        for path in other.modified_times(&searcher)?.keys() {
+            self.delete_path(writer, path.as_str());
+        }
+
+        // in the order they were added, which keeps chapters in order
+        let mut addresses: Vec<_> = searcher
+            .search(&AllQuery, &DocSetCollector)?
+            .into_iter()
+            .collect();
+        addresses.sort();
+
+        for &address in &addresses {
+            let file = AudioFile::tantivy_recall(&other.schema, &searcher.doc(address)?);
+
+            // what is only indexed (ngrams, values) is made again from what is stored
+            writer.add_document(file.tantivy_store(&self.schema))?;
+        }
+
+        Ok(addresses.len())
+    }
+
+    /// the file (and any chapters of it) indexed under exactly path, which is case sensitive and
+    /// has to be the path as indexed, canonical unless `--canonicalize false` was passed
+    pub fn lookup(&self, searcher: &Searcher, path: &str) -> tantivy::Result<Vec<AudioFile>> {
+        let query = TermQuery::new(
+            Term::from_field_text(self.schema.path_raw, path),
+            IndexRecordOption::Basic,
+        );
+
+        let mut addresses: Vec<_> = searcher
+            .search(&query, &DocSetCollector)?
+            .into_iter()
+            .collect();
+        addresses.sort();
+
+        addresses
+            .into_iter()
+            .map(|address| {
+                Ok(AudioFile::tantivy_recall(
+                    &self.schema,
+                    &searcher.doc(address)?,
+                ))
+            })
+            .collect()
+    }
+
+    /// the limit files sharing the most terms with file's title, artist, album and genre, best
+    /// first and leaving out file itself
+    pub fn similar(
+        &self,
+        searcher: &Searcher,
+        file: &AudioFile,
+        limit: usize,
+    ) -> tantivy::Result<Vec<AudioFile>> {
+        let text = |v: &Option<String>| v.iter().map(|s| OwnedValue::from(s.as_str())).collect();
+
+        let fields = vec![
+            (self.schema.title, text(&file.title)),
+            (
+                self.schema.artist,
+                text(&file.artist.clone().or(file.album_artist.clone())),
+            ),
+            (self.schema.album, text(&file.album)),
+            (
+                self.schema.genre,
+                file.genre
+                    .iter()
+                    .map(|g| OwnedValue::from(g.as_str()))
+                    .collect(),
+            ),
+        ];
+
+        // every ngram appears once in a short tag and any that only file has says nothing
+        let query = MoreLikeThisQuery::builder()
+            .with_min_term_frequency(1)
+            .with_min_doc_frequency(2)
+            .with_document_fields(fields);
+
+        // one more, as file itself is likely among them. as in top_docs, TopDocs panics on 0
+        let candidates = limit.saturating_add(1).min(searcher.num_docs() as usize);
+        if limit == 0 || candidates == 0 {
+            return Ok(Vec::new());
+        }
+
+        searcher
+            .search(&query, &TopDocs::with_limit(candidates))?
+            .into_iter()
+            .map(|(_, address)| {
+                Ok(AudioFile::tantivy_recall(
+                    &self.schema,
+                    &searcher.doc(address)?,
+                ))
+            })
+            .filter(|res| {
+                res.as_ref().map_or(true, |f| {
+                    (&f.file_path, f.start) != (&file.file_path, file.start)
+                })
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// runs text through the same analyzer the text fields are indexed with, for seeing what a
+    /// query actually turns into
+    pub fn tokens(&self, text: &str) -> Vec<String> {
+        let tokenizer = match self
+            .index
+            .schema()
+            .get_field_entry(self.schema.item_type)
+            .field_type()
+        {
+            FieldType::Str(opts) => opts
+                .get_indexing_options()
+                .map(|i| i.tokenizer().to_owned()),
+            _ => None,
+        };
+
+        let mut analyzer = self
+            .index
+            .tokenizers()
+            .get(tokenizer.as_deref().unwrap_or(HardSchema::TOKENIZER))
+            .expect("tokenizer is registered on index creation");
+
+        let mut stream = analyzer.token_stream(text);
+        let mut tokens = Vec::new();
+
+        while let Some(token) = stream.next() {
+            tokens.push(token.text.clone());
+        }
+
+        tokens
+    }
+
+    pub fn schema(&self) -> &HardSchema {
+        &self.schema
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+}
+
+/// whether any document with the term described by info is not deleted
+fn has_alive_doc(
+    segment: &SegmentReader,
+    terms: &InvertedIndexReader,
+    info: &TermInfo,
+) -> io::Result<bool> {
+    let Some(alive) = segment.alive_bitset() else {
+        return Ok(true);
+    };
+
+    let mut postings = terms.read_postings_from_terminfo(info, IndexRecordOption::Basic)?;
+
+    while postings.doc() != TERMINATED {
+        if alive.is_alive(postings.doc()) {
+            return Ok(true);
+        }
+
+        postings.advance();
+    }
+
+    Ok(false)
+}
+
+/// parses what a user typed, normalized the same way tags are before indexing. parts of the query
+/// the parser could not make sense of are left out of it, and returned along with it
+pub fn parse_query(qp: &QueryParser, text: &str) -> (Box<dyn Query>, Vec<QueryParserError>) {
+    qp.parse_query_lenient(&nfc(expand_shorthands(text)))
+}
+
+/// seconds in `m:ss` (or `h:mm:ss`), bare seconds, or any of hours, minutes and seconds in that
+/// order as in `1h2m`, `2m30s` or `90s`
+pub fn parse_duration(value: &str) -> Option<u64> {
+    if value.contains(':') {
+        return value.split(':').try_fold(0u64, |secs, part| {
+            secs.checked_mul(60)?.checked_add(part.parse().ok()?)
+        });
+    }
+
+    if value.bytes().all(|b| b.is_ascii_digit()) {
+        return value.parse().ok();
+    }
+
+    let mut rest = value;
+    let mut secs = 0u64;
+
+    for (unit, unit_secs) in [('h', 60 * 60), ('m', 60), ('s', 1)] {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+
+        if digits > 0 && rest[digits..].starts_with(unit) {
+            let n: u64 = rest[..digits].parse().ok()?;
+            secs = secs.checked_add(n.checked_mul(unit_secs)?)?;
+            rest = &rest[digits + 1..];
+        }
+    }
+
+    rest.is_empty().then_some(secs)
+}
+
+/// rewrites `len:` terms into `duration_secs` ones, so `len:>3:30`, `len:<90s` and `len:2m` can be
+/// typed instead of counting seconds, and `added:` terms into `modified` ranges, see
+/// [`added_range`]. either that does not parse is left for the parser to complain about
+fn expand_shorthands(text: &str) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    text.split(' ')
+        .map(|word| {
+            // +len:.., -len:.. and (len:..) keep what surrounds them
+            let body = word.trim_start_matches(['+', '-', '(']);
+            let prefix = &word[..word.len() - body.len()];
+            let inner = body.trim_end_matches(')');
+            let suffix = &body[inner.len()..];
+
+            if let Some(value) = inner.strip_prefix("added:") {
+                return match added_range(value, now) {
+                    Some(range) => format!("{prefix}{}:{range}{suffix}", HardSchema::MODIFIED),
+                    None => word.to_owned(),
+                };
+            }
+
+            let Some(value) = inner.strip_prefix("len:") else {
+                return word.to_owned();
+            };
+
+            let op_len = value
+                .find(|c: char| !matches!(c, '<' | '>' | '='))
+                .unwrap_or(0);
+            let (op, value) = value.split_at(op_len);
+
+            match parse_duration(value) {
+                Some(secs) => format!("{prefix}{}:{op}{secs}{suffix}", HardSchema::DURATION),
+                None => word.to_owned(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the range of mtimes an `added:` value means as of now, in query syntax. `today` and
+/// `yesterday` go by local midnight, `last-week`, `last-month` and `last-year` are the last 7, 30
+/// and 365 days, and an age like `30d` (also in `h`, `w` or `y`) is anything newer than that,
+/// `<30d` the same and `>30d` anything older
+fn added_range(value: &str, now: u64) -> Option<String> {
+    const DAY: u64 = 24 * 60 * 60;
+
+    let since = |secs: u64| Some(format!(">={}", now.saturating_sub(secs)));
+
+    match value {
+        "today" => return Some(format!(">={}", local_midnight(now))),
+        "yesterday" => {
+            let midnight = local_midnight(now);
+            return Some(format!("[{} TO {midnight}}}", midnight.saturating_sub(DAY)));
+        }
+        "last-week" => return since(7 * DAY),
+        "last-month" => return since(30 * DAY),
+        "last-year" => return since(365 * DAY),
+        _ => (),
+    }
+
+    let (older, age) = match value.strip_prefix('>') {
+        Some(age) => (true, age),
+        None => (false, value.strip_prefix('<').unwrap_or(value)),
+    };
+
+    let unit = match age.chars().last()? {
+        'h' => 60 * 60,
+        'd' => DAY,
+        'w' => 7 * DAY,
+        'y' => 365 * DAY,
+        _ => return None,
+    };
+
+    let secs = age[..age.len() - 1]
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(unit)?;
+
+    if older {
+        Some(format!("<{}", now.saturating_sub(secs)))
+    } else {
+        since(secs)
+    }
+}
+
+/// the start of the local day now is in, as seconds since the epoch
+#[cfg(unix)]
+fn local_midnight(now: u64) -> u64 {
+    let Ok(t) = libc::time_t::try_from(now) else {
+        return now;
+    };
+
+    // SAFETY: tm is plain data and localtime_r only writes into it
+    let tm = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+
+        if libc::localtime_r(&t, &mut tm).is_null() {
+            return now - now % (24 * 60 * 60);
+        }
+
+        tm
+    };
+
+    let into_day = u64::try_from(tm.tm_hour * 60 * 60 + tm.tm_min * 60 + tm.tm_sec).unwrap_or(0);
+
+    now.saturating_sub(into_day)
+}
+
+/// the start of the utc day now is in, there is no local timezone to go by here
+#[cfg(not(unix))]
+fn local_midnight(now: u64) -> u64 {
+    now - now % (24 * 60 * 60)
+}
+
+/// puts each run of hits with the same score in a stable order, by path or with folder_order by
+/// [`AudioFile::cmp_folder_order`]. hits are already in rank order, so only neighbouring ties are
+/// reordered. every way hits are ordered goes through this, so results never differ by how they
+/// were searched
+pub fn order_ties<T>(hits: &mut [(Score, T)], file: impl Fn(&T) -> &AudioFile, folder_order: bool) {
+    for ties in hits.chunk_by_mut(|(sa, _), (sb, _)| sa == sb) {
+        ties.sort_by(|(_, a), (_, b)| match folder_order {
+            true => file(a).cmp_folder_order(file(b)),
+            false => file(a).cmp_tie_break(file(b)),
+        });
+    }
+}
+
+/// rewrites text so the query parser takes all of it as plain words, with characters like `:`,
+/// `[` or `(` searched for rather than read as query syntax. each word becomes a quoted phrase,
+/// the only place the parser accepts escapes
+pub fn literal_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// every start of 3 letters or more of each word. one ngram from the start of each tag would
+/// have "beatles" found by "beach boys", with every letter of the word kept "beatles" has to
+/// start a word the way it is spelled out, and "bea" still finds both
+#[derive(Clone, Default)]
+struct WordPrefixTokenizer(SimpleTokenizer);
+
+impl Tokenizer for WordPrefixTokenizer {
+    type TokenStream<'a> = PreTokenizedStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> PreTokenizedStream {
+        let mut words = self.0.token_stream(text);
+        let mut tokens = Vec::new();
+
+        while let Some(word) = words.next() {
+            let ends = word.text.char_indices().map(|(i, c)| i + c.len_utf8());
+
+            for end in ends.skip(2) {
+                tokens.push(Token {
+                    offset_from: word.offset_from,
+                    offset_to: word.offset_from + end,
+                    // as with ngrams, a query matches where all of its tokens are found
+                    position: 0,
+                    text: word.text[..end].to_owned(),
+                    position_length: 1,
+                });
+            }
+        }
+
+        PreTokenizedString {
+            text: text.to_owned(),
+            tokens,
+        }
+        .into()
+    }
+}
+
+fn register_tokenizer(index: &Index, opts: &SchemaOptions) {
+    index.tokenizers().register(
+        HardSchema::TOKENIZER,
+        TextAnalyzer::builder(
+            NgramTokenizer::new(3, 3, false)
+                .expect("this tokenizer will not error with these arguments"),
+        )
+        .filter(tantivy::tokenizer::LowerCaser)
+        .filter(StopWordFilter::remove(opts.ngram_stop.iter().cloned()))
+        .build(),
+    );
+
+    index.tokenizers().register(
+        HardSchema::PREFIX_TOKENIZER,
+        TextAnalyzer::builder(WordPrefixTokenizer::default())
+            .filter(tantivy::tokenizer::LowerCaser)
+            .filter(StopWordFilter::remove(opts.ngram_stop.iter().cloned()))
+            .build(),
+    );
+
+    index.tokenizers().register(
+        HardSchema::KEYWORD_TOKENIZER,
+        TextAnalyzer::builder(RawTokenizer::default())
+            .filter(tantivy::tokenizer::LowerCaser)
+            .build(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titled(titles: &[&str]) -> MusicIndex {
+        MusicIndex::from_audiofiles(titles.iter().enumerate().map(|(i, title)| {
+            AudioFile::builder(format!("/music/{i}.flac"))
+                .title(*title)
+                .build()
+        }))
+        .unwrap()
+    }
+
+    fn titles(index: &MusicIndex, text: &str, limit: usize, rank: Rank) -> Vec<String> {
+        let searcher = index.reader().unwrap().searcher();
+        let (query, _) = parse_query(&index.query_parser(), text);
+
+        index
+            .search(&searcher, &*query, text, limit, rank, &[])
+            .unwrap()
+            .into_iter()
+            .filter_map(|(_, f)| f.title)
+            .collect()
+    }
+
+    #[test]
+    fn prefix_only_matches_the_start_of_each_word() {
+        let index = MusicIndex::create_in_ram(&SchemaOptions {
+            ngram_prefix_only: true,
+            ..SchemaOptions::default()
+        });
+
+        let mut writer = index.writer().unwrap();
+        for title in ["Beatles Forever", "Beach Boys"] {
+            writer
+                .add_document(
+                    AudioFile::builder(format!("/music/{title}.flac"))
+                        .title(title)
+                        .build()
+                        .tantivy_store(&index.schema),
+                )
+                .unwrap();
+        }
+        index.commit(&mut writer).unwrap();
+
+        assert_eq!(
+            titles(&index, "beatles", 10, Rank::Bm25),
+            ["Beatles Forever"]
+        );
+        assert_eq!(titles(&index, "boys", 10, Rank::Bm25), ["Beach Boys"]);
+        assert_eq!(titles(&index, "bea", 10, Rank::Bm25).len(), 2);
+        assert!(titles(&index, "eatl", 10, Rank::Bm25).is_empty());
+        assert_eq!(index.tokens("ABBA"), ["abb", "abba"]);
+    }
+
+    fn sorted(
+        files: &[(&str, Option<&str>, Option<u8>)],
+        sort: &[(SortKey, Order)],
+    ) -> Vec<String> {
+        let mut files: Vec<AudioFile> = files
+            .iter()
+            .map(|&(path, artist, rating)| {
+                let mut file = AudioFile::builder(path).build();
+                file.artist = artist.map(str::to_owned);
+                file.rating = rating;
+                file
+            })
+            .collect();
+
+        files.sort_by(|a, b| SortKey::cmp_by(sort, a, b));
+
+        files
+            .into_iter()
+            .map(|f| f.file_path.into_string())
+            .collect()
+    }
+
+    #[test]
+    fn sorting_by_several_keys_is_stable() {
+        let files = [
+            ("/1", Some("beta"), Some(3)),
+            ("/2", Some("Alpha"), Some(3)),
+            ("/3", Some("alpha"), Some(5)),
+            ("/4", Some("beta"), Some(3)),
+            ("/5", Some("alpha"), Some(3)),
+        ];
+
+        let sort = [
+            (SortKey::Artist, Order::Asc),
+            (SortKey::Rating, Order::Desc),
+        ];
+
+        // artists ignore case, and what ties on both keeps the order it came in
+        assert_eq!(sorted(&files, &sort), ["/3", "/2", "/5", "/1", "/4"]);
+        assert_eq!(sorted(&files, &[]), ["/1", "/2", "/3", "/4", "/5"]);
+    }
+
+    #[test]
+    fn missing_values_sort_last_either_way() {
+        let files = [
+            ("/1", None, None),
+            ("/2", Some("b"), Some(1)),
+            ("/3", None, None),
+            ("/4", Some("a"), Some(2)),
+        ];
+
+        for order in [Order::Asc, Order::Desc] {
+            let paths = sorted(&files, &[(SortKey::Artist, order.clone())]);
+            assert_eq!(&paths[2..], ["/1", "/3"], "{order:?}");
+
+            let paths = sorted(&files, &[(SortKey::Rating, order.clone())]);
+            assert_eq!(&paths[2..], ["/1", "/3"], "{order:?}");
+        }
+
+        assert_eq!(
+            sorted(&files, &[(SortKey::Artist, Order::Desc)])[..2],
+            ["/2", "/4"]
+        );
+    }
+
+    #[test]
+    fn ties_come_out_in_path_order() {
+        let index = MusicIndex::from_audiofiles(["/c", "/a", "/d", "/b"].map(|path| {
+            AudioFile::builder(format!("{path}.flac"))
+                .title("Same")
+                .build()
+        }))
+        .unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let (query, _) = parse_query(&index.query_parser(), "same");
+
+        let paths: Vec<String> = index
+            .search(&searcher, &*query, "same", 10, Rank::Bm25, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(_, f)| f.file_path.into_string())
+            .collect();
+        assert_eq!(paths, ["/a.flac", "/b.flac", "/c.flac", "/d.flac"]);
+
+        // only neighbouring ties move, a better hit stays first
+        let file = |path: &str, track| AudioFile::builder(path).track(track).build();
+        let mut hits = vec![
+            (2.0, file("/z/1.flac", 1)),
+            (1.0, file("/b/1.flac", 2)),
+            (1.0, file("/b/2.flac", 1)),
+            (1.0, file("/a/9.flac", 3)),
+        ];
+
+        order_ties(&mut hits, |f| f, true);
+
+        let paths: Vec<&str> = hits.iter().map(|(_, f)| f.file_path.as_str()).collect();
+        assert_eq!(paths, ["/z/1.flac", "/a/9.flac", "/b/2.flac", "/b/1.flac"]);
+    }
+
+    #[test]
+    fn exact_rank_puts_whole_titles_first() {
+        let index = titled(&["Love Love Love Me Do Love", "Love"]);
+
+        assert_eq!(titles(&index, "love", 10, Rank::Exact)[0], "Love");
+    }
+
+    fn values(index: &MusicIndex, name: &str) -> Vec<String> {
+        let mut values = Vec::new();
+
+        index
+            .for_each_value(&index.reader().unwrap().searcher(), name, |v| {
+                values.push(v.to_owned());
+                true
+            })
+            .unwrap();
+
+        values
+    }
+
+    #[test]
+    fn values_across_segments() {
+        let index = MusicIndex::create_in_ram(&SchemaOptions::default());
+        let mut writer: IndexWriter = index.writer().unwrap();
+
+        let by = |path: &str, artist: &str| {
+            AudioFile::builder(path)
+                .artist(artist)
+                .genre("Rock")
+                .build()
+                .tantivy_store(index.schema())
+        };
+
+        // one commit per segment, with artists in and across both
+        writer.add_document(by("/a.flac", "The Beatles")).unwrap();
+        writer.add_document(by("/b.flac", "ABBA")).unwrap();
+        writer.commit().unwrap();
+        writer.add_document(by("/c.flac", "The Beatles")).unwrap();
+        writer.add_document(by("/d.flac", "Queen")).unwrap();
+        writer.add_document(by("/e.flac", "Gone")).unwrap();
+        writer.commit().unwrap();
+
+        assert_eq!(
+            values(&index, HardSchema::ARTIST),
+            ["ABBA", "Gone", "Queen", "The Beatles"]
+        );
+        // whole and as tagged, though genres are matched ignoring case
+        assert_eq!(values(&index, HardSchema::GENRE), ["Rock"]);
+        assert!(values(&index, HardSchema::ALBUM).is_empty());
+
+        index.delete_path(&writer, "/e.flac");
+        writer.commit().unwrap();
+
+        assert_eq!(
+            values(&index, HardSchema::ARTIST),
+            ["ABBA", "Queen", "The Beatles"]
+        );
+    }
+
+    #[test]
+    fn values_stop_when_asked() {
+        let index = MusicIndex::from_audiofiles(
+            ["a", "b", "c"].map(|t| AudioFile::builder(format!("/{t}.flac")).title(t).build()),
+        )
+        .unwrap();
+
+        let mut seen = Vec::new();
+        index
+            .for_each_value(&index.reader().unwrap().searcher(), "title", |v| {
+                seen.push(v.to_owned());
+                seen.len() < 2
+            })
+            .unwrap();
+
+        assert_eq!(seen, ["a", "b"]);
+        assert!(index
+            .for_each_value(&index.reader().unwrap().searcher(), "path", |_| true)
+            .is_err());
+    }
+
+    #[test]
+    fn recent_with_no_limit() {
+        let index = titled(&["a"]);
+        let searcher = index.reader().unwrap().searcher();
+
+        assert!(index.recent(&searcher, 0).unwrap().is_empty());
+        assert_eq!(index.recent(&searcher, usize::MAX).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn limits_past_the_document_count() {
+        let index = titled(&["Love", "Love Me Do"]);
+
+        for rank in [Rank::Bm25, Rank::Exact] {
+            assert_eq!(titles(&index, "love", usize::MAX, rank).len(), 2);
+            assert!(titles(&index, "love", 0, rank).is_empty());
+        }
+
+        let empty = titled(&[]);
+        assert!(titles(&empty, "love", 10, Rank::Exact).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deletes_only_what_is_under_a_dir() {
+        let index = MusicIndex::from_audiofiles(
+            [
+                "/music/album/01.flac",
+                "/music/album/cd 2/01.flac",
+                "zip:///music/album/rips.zip!01.flac",
+                "/music/album 2/01.flac",
+                "/music/albums/01.flac",
+                "/music/01.flac",
+            ]
+            .map(|path| AudioFile::builder(path).build()),
+        )
+        .unwrap();
+
+        let mut writer = index.writer().unwrap();
+        index
+            .delete_under(&writer, Utf8Path::new("/music/album/"))
+            .unwrap();
+        writer.commit().unwrap();
+
+        let reader = index.reader().unwrap();
+        reader.reload().unwrap();
+
+        let mut left: Vec<_> = index
+            .modified_times(&reader.searcher())
+            .unwrap()
+            .into_keys()
+            .collect();
+        left.sort();
+
+        assert_eq!(
+            left,
+            [
+                "/music/01.flac",
+                "/music/album 2/01.flac",
+                "/music/albums/01.flac"
+            ]
+        );
+    }
+
+    #[test]
+    fn merging_takes_a_file_from_the_last_index_it_is_in() {
+        let first = MusicIndex::from_audiofiles([
+            AudioFile::builder("/a/1.flac").title("yellow").build(),
+            AudioFile::builder("/both.flac").title("blue").build(),
+        ])
+        .unwrap();
+        let second =
+            MusicIndex::from_audiofiles([AudioFile::builder("/both.flac").title("green").build()])
+                .unwrap();
+
+        let merged = MusicIndex::create_in_ram(&SchemaOptions::default());
+        let mut writer = merged.writer().unwrap();
+        assert_eq!(merged.merge_from(&writer, &first).unwrap(), 2);
+        assert_eq!(merged.merge_from(&writer, &second).unwrap(), 1);
+        merged.commit(&mut writer).unwrap();
+
+        // searched by ngrams, which only the stored titles were left to make them from
+        let mut found = titles(&merged, "yellow green blue", 10, Rank::Bm25);
+        found.sort();
+        assert_eq!(found, ["green", "yellow"]);
+    }
+
+    #[test]
+    fn merging_needs_the_same_fields() {
+        let other = MusicIndex::create_in_ram(&SchemaOptions {
+            no_extras: true,
+            ..SchemaOptions::default()
+        });
+        let merged = MusicIndex::create_in_ram(&SchemaOptions::default());
+
+        let writer = merged.writer().unwrap();
+        assert!(merged.merge_from(&writer, &other).is_err());
+    }
+
+    #[test]
+    fn saved_indexes_open_with_their_options() {
+        let dir = crate::ScratchDir::new().unwrap();
+        let opts = SchemaOptions {
+            ngram_prefix_only: true,
+            ..SchemaOptions::default()
+        };
+
+        assert!(MusicIndex::open_in_dir(dir.path()).is_err());
+
+        MusicIndex::open_or_create_in_dir(dir.path(), &opts).unwrap();
+        let (_, built_with) = MusicIndex::open_in_dir(dir.path()).unwrap();
+        assert!(built_with.differences(&opts).is_empty());
+    }
+
+    #[test]
+    fn saved_indexes_are_searched_while_written_elsewhere() {
+        let dir = crate::ScratchDir::new().unwrap();
+        let opts = SchemaOptions::default();
+
+        // as if opened by two processes, one writing and one searching
+        let (writing, _) = MusicIndex::open_or_create_in_dir(dir.path(), &opts).unwrap();
+        let (searching, _) = MusicIndex::open_or_create_in_dir(dir.path(), &opts).unwrap();
+
+        let mut writer = writing.writer().unwrap();
+        assert!(matches!(
+            searching.writer(),
+            Err(TantivyError::LockFailure(..))
+        ));
+
+        let reader = searching.reader().unwrap();
+        assert_eq!(reader.searcher().num_docs(), 0);
+
+        writer
+            .add_document(
+                AudioFile::builder("/a.flac")
+                    .build()
+                    .tantivy_store(&writing.schema),
+            )
+            .unwrap();
+        writing.commit(&mut writer).unwrap();
+
+        reader.reload().unwrap();
+        assert_eq!(reader.searcher().num_docs(), 1);
+        assert!(searching.committed().unwrap().is_some());
+    }
+
+    #[test]
+    fn durations_in_every_form() {
+        for (value, secs) in [
+            ("3:30", 210),
+            ("1:02:03", 3723),
+            ("90", 90),
+            ("90s", 90),
+            ("5m", 300),
+            ("1h2m", 3720),
+            ("2m30s", 150),
+            ("1h", 3600),
+            ("1h0m5s", 3605),
+        ] {
+            assert_eq!(parse_duration(value), Some(secs), "{value}");
+        }
+
+        for value in [
+            "",
+            "m",
+            "abc",
+            "5x",
+            "3:3a",
+            "2m1h",
+            "1h1h",
+            "-5",
+            "5 m",
+            "99999999999999999999m",
+        ] {
+            assert_eq!(parse_duration(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn len_shorthands_become_duration_ranges() {
+        for (query, expanded) in [
+            ("len:3:30", "duration_secs:210"),
+            ("len:1h2m", "duration_secs:3720"),
+            ("len:>5m", "duration_secs:>300"),
+            ("+len:<=90s love", "+duration_secs:<=90 love"),
+            ("(len:>=2m)", "(duration_secs:>=120)"),
+        ] {
+            assert_eq!(expand_shorthands(query), expanded, "{query}");
+        }
+
+        // left for the parser to complain about
+        for query in ["len:soon", "len:>", "len:5x"] {
+            assert_eq!(expand_shorthands(query), query, "{query}");
+        }
+    }
+
+    #[test]
+    fn added_ranges_as_of_now() {
+        const DAY: u64 = 24 * 60 * 60;
+        let now = 1_700_000_000;
+
+        assert_eq!(
+            added_range("30d", now),
+            Some(format!(">={}", now - 30 * DAY))
+        );
+        assert_eq!(
+            added_range("<2w", now),
+            Some(format!(">={}", now - 14 * DAY))
+        );
+        assert_eq!(
+            added_range(">1y", now),
+            Some(format!("<{}", now - 365 * DAY))
+        );
+        assert_eq!(
+            added_range("12h", now),
+            Some(format!(">={}", now - 12 * 60 * 60))
+        );
+        assert_eq!(
+            added_range("last-week", now),
+            Some(format!(">={}", now - 7 * DAY))
+        );
+        assert_eq!(
+            added_range("last-month", now),
+            Some(format!(">={}", now - 30 * DAY))
+        );
+
+        let midnight = local_midnight(now);
+        assert_eq!(added_range("today", now), Some(format!(">={midnight}")));
+        assert_eq!(
+            added_range("yesterday", now),
+            Some(format!("[{} TO {midnight}}}", midnight - DAY))
+        );
+
+        // an age past the epoch is everything
+        assert_eq!(added_range("999999y", now), Some(">=0".into()));
+
+        for value in [
+            "",
+            "soon",
+            "30",
+            "d",
+            "30x",
+            ">",
+            "-3d",
+            "3.5d",
+            "last-decade",
+        ] {
+            assert_eq!(added_range(value, now), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn local_midnight_starts_the_day() {
+        for now in [0, 1_700_000_000, 1_700_000_000 + 12 * 60 * 60] {
+            let midnight = local_midnight(now);
+
+            assert!(midnight <= now);
+            assert!(now - midnight < 24 * 60 * 60);
+            assert_eq!(local_midnight(midnight), midnight);
+        }
+    }
+
+    #[test]
+    fn options_are_the_same_in_any_order() {
+        let dir = crate::ScratchDir::new().unwrap();
+        let opts = |fields: &[&str], tags: &[&str]| SchemaOptions {
+            keyword_fields: fields.iter().map(|&f| f.to_owned()).collect(),
+            promoted_tags: tags.iter().map(|&t| t.to_owned()).collect(),
+            ..SchemaOptions::default()
+        };
+
+        MusicIndex::open_or_create_in_dir(
+            dir.path(),
+            &opts(&["genre", "album"], &["mood", "label", "mood"]),
+        )
+        .unwrap();
+
+        let (_, built_with) = MusicIndex::open_in_dir(dir.path()).unwrap();
+        assert_eq!(built_with.keyword_fields, ["album", "genre"]);
+        assert_eq!(built_with.promoted_tags, ["label", "mood"]);
+
+        let (_, created) = MusicIndex::open_or_create_in_dir(
+            dir.path(),
+            &opts(&["album", "genre"], &["label", "mood"]),
+        )
+        .unwrap();
+        assert!(!created);
+
+        let other = opts(&["genre"], &["mood", "label"]);
+        assert_eq!(built_with.differences(&other), ["keyword_fields"]);
+        assert!(MusicIndex::open_or_create_in_dir(dir.path(), &other).is_err());
+    }
+}
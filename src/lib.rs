@@ -0,0 +1,24 @@
+//! indexing and querying half of musicsearch, kept separate from the cli so benches can drive it
+//! without ffmpeg or a terminal
+
+mod archive;
+mod audio;
+mod index;
+mod playlist;
+pub mod remote;
+mod schema;
+mod theme;
+
+pub use archive::{audio_entries, extract_entry, split_archive_path, ScratchDir};
+pub use audio::{
+    classify_tag, count_files, extract_cover, indexed_dir, playlist_audiofiles, read_file,
+    recursive_find_audiofiles, without_leading_article, AlbumKey, AudioFile, ScanOptions,
+    ShowField, Shown, Sidecar, TagField, Unreadable, YearSource, AUDIO_EXT,
+};
+pub use index::{
+    literal_query, order_ties, parse_duration, parse_query, MusicIndex, Rank, Skipped, SortKey,
+};
+pub use playlist::{read_playlist, PlaylistEntry};
+pub use remote::RemoteIndex;
+pub use schema::{HardSchema, SchemaOptions};
+pub use theme::Theme;
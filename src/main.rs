@@ -1,465 +1,1979 @@
-use core::fmt;
-use std::{collections::HashMap, fmt::Display, io, process, time::Instant};
-
-use camino::{Utf8Path, Utf8PathBuf};
-use clap::Parser;
-use rayon::iter::{ParallelBridge, ParallelIterator};
-use rustyline::{config::Configurer, DefaultEditor};
-use tantivy::{
-    collector::TopDocs,
-    query::QueryParser,
-    schema::{
-        Field, FieldValue, IndexRecordOption, OwnedValue, Schema, TextFieldIndexing, Value,
-        INDEXED, STORED, TEXT,
+mod term;
+mod ui;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, Write},
+    num::NonZeroUsize,
+    process::ExitCode,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, PoisonError,
     },
-    tokenizer::TextAnalyzer,
-    Document, IndexWriter, Opstamp, TantivyDocument,
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
-use walkdir::WalkDir;
-
-const AUDIO_EXT: phf::Set<&'static str> = phf::phf_set! {
-    // trash
-    "mp3",
-
-    // open codecs/containers
-    "flac",
-    "opus",
-    "ape",
-    "ogg",
-    "mka",
-    "webm",
-
-    // apple stuff
-    "aac",
-    "alac",
-    "m4a",
-    "caf",
-
-    // windows stuff
-    "wma",
-    "wav",
+
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{builder::TypedValueParser, ArgGroup, Parser};
+use indicatif::{ProgressBar, ProgressStyle};
+use musicsearch::{
+    audio_entries, classify_tag, count_files, indexed_dir, parse_duration, playlist_audiofiles,
+    read_file, read_playlist, recursive_find_audiofiles, remote, split_archive_path, AlbumKey,
+    AudioFile, HardSchema, MusicIndex, Rank, RemoteIndex, ScanOptions, SchemaOptions, ScratchDir,
+    ShowField, Sidecar, Skipped, SortKey, TagField, Theme, Unreadable, YearSource,
 };
+use rayon::iter::ParallelIterator;
+use tantivy::{query::QueryParser, IndexReader, IndexWriter, Order, Searcher, TantivyError};
+use tracing::{debug, info, trace, warn};
+use tracing_subscriber::EnvFilter;
 
-struct AlbumKey {
-    ordered_paths: Vec<Utf8PathBuf>,
+#[derive(clap::Parser)]
+#[command(after_help = EXIT_CODES)]
+#[command(group(ArgGroup::new("sources").multiple(true).args(["dir", "library", "from_playlist"])))]
+#[command(group(ArgGroup::new("answers").args(["query", "rpc"])))]
+/// A music search engine utilizing ffmpeg and tantivy to gather and query songs
+struct Args {
+    /// dirs to recurse into to find music
+    #[arg(num_args = 1..)]
+    dir: Vec<Utf8PathBuf>,
 
-    album_name: String,
+    /// index a dir as a library of this name, as in `--library vinyl=~/rips`, so `lib:vinyl`
+    /// finds only what is in it. dirs passed without a name are named after their last component
+    #[arg(long, value_name = "NAME=DIR", value_parser = library_arg)]
+    library: Vec<(String, Utf8PathBuf)>,
+
+    /// keep the index in this dir, creating it there the first time, so later runs search it as it
+    /// is without reading a file. that is only when no dirs or playlists are passed, the ones that
+    /// are get walked again and files new or changed since are read. `:index` adds to it too
+    #[arg(long, value_name = "DIR")]
+    index_path: Option<Utf8PathBuf>,
+
+    /// with `--index-path`, keep the saved index up to date with the passed dirs instead of
+    /// starting a ui, walking them again this often (as in `--serve-index 5m`) and committing what
+    /// changed. musicsearch started with the same `--index-path` and no dirs searches it meanwhile
+    /// and sees each commit as it is made. a path written to stdin, one a line, is read again (and
+    /// committed) right away on its own. playlists are only read the first time
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = duration_arg,
+        requires = "index_path",
+        conflicts_with_all = [
+            "serve", "connect", "rpc", "query", "lookup", "dump", "dump_terms", "merge",
+        ]
+    )]
+    serve_index: Option<u64>,
+
+    /// with `--index-path`, throw away what the saved index holds and read every file again.
+    /// needs the dirs or playlists to read, or it would only empty the index
+    #[arg(long, requires_all = ["index_path", "sources"])]
+    reindex: bool,
+
+    /// with `--index-path`, throw away what the saved index holds under this dir and read every
+    /// file in it again, without walking anything else. files keep the library they were indexed
+    /// with, and other dirs passed are updated as usual
+    #[arg(
+        long,
+        value_name = "DIR",
+        requires = "index_path",
+        conflicts_with = "reindex"
+    )]
+    reindex_path: Vec<Utf8PathBuf>,
+
+    /// index exactly the tracks an m3u or pls playlist lists, relative paths are taken from the
+    /// playlist's dir. can be passed more than once and along with dirs
+    #[arg(long, value_name = "FILE")]
+    from_playlist: Vec<Utf8PathBuf>,
+
+    /// max number of results to show per search, at least 1
+    #[arg(
+        long,
+        default_value_t = 15,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+    )]
+    limit: usize,
+
+    /// command used by `:open` to open a result, the path replaces `{}` if present and is
+    /// appended otherwise, `{start}` is replaced by the seconds a chapter starts at (or 0)
+    #[arg(long, default_value = ui::DEFAULT_OPENER)]
+    open_with: String,
+
+    /// command used by `:folder` to open the folder a result is in, the folder replaces `{}` if
+    /// present and is appended otherwise
+    #[arg(long, default_value = ui::DEFAULT_OPENER)]
+    folder_open_with: String,
+
+    /// under each result, show where a query matched fields that are not shown in full, such as
+    /// directories in the path or lyrics and other tags kept in extras
+    #[arg(long)]
+    snippets: bool,
+
+    /// fields to show for each result, in this order. the full path is still in the hyperlink when
+    /// it is left out
+    #[arg(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        default_value = "path,title,work,start,artist,album,track,date,genre,channels,bpm,chapters,rating",
+        value_parser = clap::builder::PossibleValuesParser::new(ShowField::NAMES)
+            .map(|s| s.parse::<ShowField>().expect("only known names get this far")),
+    )]
+    show_fields: Vec<ShowField>,
+
+    /// colors to show results in, `mono` prints none
+    #[arg(
+        long,
+        default_value = "default",
+        value_parser = clap::builder::PossibleValuesParser::new(Theme::NAMES)
+            .map(|s| s.parse::<Theme>().expect("only known names get this far")),
+    )]
+    theme: Theme,
+
+    /// what to print between results, which helps with long results and large limits
+    #[arg(long, value_enum, default_value = "none")]
+    separator: Separator,
+
+    /// print the search and how many results it found above the results at the prompt
+    #[arg(long)]
+    header: bool,
+
+    /// print songs from the same album together under one header naming it, groups are ordered by
+    /// their best result. results are shown all at once instead of as they are read
+    #[arg(long, value_enum, value_name = "FIELD")]
+    group_by: Option<GroupBy>,
+
+    /// with `--group-by album`, take "The Beatles" and "Beatles" to be the same album artist.
+    /// shown names keep their article
+    #[arg(long)]
+    ignore_leading_article: bool,
+
+    /// with `--group-by album`, what compilations are credited to. albums by "VA", "Various" and
+    /// other common spellings are all credited to this, so they group together
+    #[arg(long, default_value = AlbumKey::VARIOUS_ARTISTS, value_name = "LABEL")]
+    various_artists_label: String,
+
+    /// show a song found more than once (rips from several albums, several formats) only once,
+    /// preferring a lossless copy and otherwise the best ranked one
+    #[arg(long)]
+    dedup_results: bool,
+
+    /// leave songs matching any of these comma separated terms out of every search, unless the
+    /// search itself has the term in it. `:exclude` changes them at the prompt
+    #[arg(long, value_name = "TERMS", value_delimiter = ',')]
+    exclude: Vec<String>,
+
+    /// list results that score the same in folder and track order, so an album comes back as
+    /// tracks 1, 2, 3 rather than in path order, which puts "10 ..." before "2 ...". results
+    /// scoring differently stay in score order, so a track matching the query better still
+    /// comes first, use `--sort album,track` to ignore scores entirely
+    #[arg(long)]
+    preserve_order: bool,
+
+    /// order results by these comma separated keys instead of by how well they match, each
+    /// `KEY`, `KEY:asc` or `KEY:desc`, as in `--sort rating,artist`. ratings and `modified` go
+    /// highest first unless `:asc` is given, and how well results match breaks remaining ties
+    #[arg(long, value_delimiter = ',', value_name = "KEY[:ORDER]", value_parser = sort_arg)]
+    sort: Vec<(SortKey, Order)>,
+
+    /// searches at the prompt shorter than this many characters are not run, as they match next to
+    /// nothing or next to everything. `--query` is always run
+    #[arg(long, default_value_t = 2, value_name = "N")]
+    min_query_len: usize,
+
+    /// while typing a search, show its best match and how many songs match after the cursor
+    #[arg(long)]
+    hints: bool,
+
+    /// let long results wrap instead of cutting them off at the terminal width
+    #[arg(long)]
+    no_truncate: bool,
+
+    /// search only this field unless a query names one itself, so `--field title` takes
+    /// `abbey road` as `title:abbey title:road`. may be passed more than once to search several
+    #[arg(
+        long,
+        value_name = "FIELD",
+        conflicts_with = "connect",
+        value_parser = clap::builder::PossibleValuesParser::new(HardSchema::TEXT_FIELDS),
+    )]
+    field: Vec<String>,
+
+    /// whether every term of a query must match by default, `+term` and `-term` always require or
+    /// exclude a term either way
+    #[arg(long = "match", value_enum, default_value_t = Match::Any)]
+    match_mode: Match,
+
+    /// search for exactly what was typed, so characters like `:` `[` and `(` are matched instead of
+    /// being read as query syntax. `:literal` toggles this in the ui
+    #[arg(long)]
+    literal: bool,
+
+    /// print how each search was parsed, which fields and terms it became and which parts of it
+    /// could not be parsed and were left out, before its results. only searches of a local index
+    /// can be explained
+    #[arg(long)]
+    explain_query: bool,
+
+    /// how results are ordered, `exact` puts songs whose title, artist or album is closest to
+    /// being just the query first where `bm25` (tantivy's scoring) can favor tags repeating it
+    #[arg(
+        long,
+        default_value = "bm25",
+        value_parser = clap::builder::PossibleValuesParser::new(Rank::NAMES)
+            .map(|s| s.parse::<Rank>().expect("only known names get this far")),
+    )]
+    rank: Rank,
+
+    /// fields to keep for display without indexing them, shrinking the index but making them
+    /// unsearchable
+    #[arg(
+        long = "index-fields-stored-only",
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        value_parser = clap::builder::PossibleValuesParser::new(HardSchema::TEXT_FIELDS),
+    )]
+    stored_only: Vec<String>,
+
+    /// text fields to match whole (ignoring case) instead of by ngrams, so `genre:rock` finds
+    /// rock but not prog rock or rockabilly. `none` matches every field by ngrams
+    #[arg(
+        long = "index-fields-keyword",
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        default_value = "genre",
+        value_parser = clap::builder::PossibleValuesParser::new(
+            HardSchema::TEXT_FIELDS.iter().copied().chain(["none"]),
+        ),
+    )]
+    keyword_fields: Vec<String>,
+
+    /// leave tags without a field of their own (lyrics, labels, comments and so on) out of the index
+    /// entirely, which shrinks it when they are never searched for
+    #[arg(long)]
+    no_extras: bool,
+
+    /// give the tag with this key a field of its own instead of keeping it in extras, so
+    /// `--promote-tag mood` makes `mood:melancholy` searchable. may be passed more than once
+    #[arg(long, value_name = "KEY", value_parser = promote_tag_arg)]
+    promote_tag: Vec<String>,
+
+    /// match queries against the start of each word only, so "bea" finds "Beatles" but "eatl" does
+    /// not, and typos lose the match from the misspelt letter on
+    #[arg(long)]
+    ngram_prefix_only: bool,
+
+    /// comma separated 3 letter ngrams to leave out of the index, such as `the,ing`, which
+    /// shrinks it and speeds up searches but means they no longer count towards a match, and a
+    /// search made only of them matches nothing. `:tokens` shows what is left of a query
+    #[arg(long, value_name = "NGRAMS", value_delimiter = ',', value_parser = ngram_stop_arg)]
+    ngram_stop: Vec<String>,
+
+    /// also index each embedded chapter (as in audiobooks) as its own result
+    #[arg(long)]
+    chapters: bool,
+
+    /// stop indexing at the first file that can not be read, an unreadable dir or a path that is
+    /// not utf-8, rather than skipping it with a warning. files that are not audio by their
+    /// extension are still skipped
+    #[arg(long)]
+    strict: bool,
+
+    /// index the passed dirs and answer searches over tcp on this address instead of starting a ui
+    #[arg(long, value_name = "ADDR")]
+    serve: Option<String>,
+
+    /// search an index served by `--serve` on another machine instead of indexing locally
+    #[arg(long, value_name = "ADDR", conflicts_with = "serve")]
+    connect: Option<String>,
+
+    /// index the passed dirs and answer searches read from stdin on stdout, in the same json as
+    /// `--serve`, instead of starting a ui
+    #[arg(long, conflicts_with_all = ["serve", "connect", "query", "lookup", "dump"])]
+    rpc: bool,
+
+    /// run this one search, print its results and exit instead of starting a ui
+    #[arg(long, value_name = "QUERY", conflicts_with = "serve")]
+    query: Option<String>,
+
+    /// with `--query` or `--rpc`, write results as json to this already open file descriptor
+    /// instead of to stdout, as in `--output-fd 3` with `3>` a fifo. unix only
+    #[arg(long, value_name = "FD", requires = "answers")]
+    output_fd: Option<i32>,
+
+    /// print everything indexed for the file at this path as json and exit, the path is matched
+    /// exactly (and case sensitively) after being made canonical
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["serve", "connect", "query"])]
+    lookup: Option<String>,
+
+    /// read the tags of just this file, print everything that was made of them as json and exit
+    /// without indexing anything
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["serve", "connect", "query", "lookup"]
+    )]
+    dump: Option<Utf8PathBuf>,
+
+    /// print every distinct value of this field in the index, one per line, and exit, for
+    /// building completions in a shell or launcher
+    #[arg(
+        long,
+        value_name = "FIELD",
+        value_parser = clap::builder::PossibleValuesParser::new(HardSchema::VALUE_FIELDS),
+        conflicts_with_all = ["serve", "connect", "query", "lookup", "dump", "rpc"]
+    )]
+    dump_terms: Option<String>,
+
+    /// combine the indexes saved in these dirs into the one `--into` names and exit, without
+    /// reading a file. they have to be built with the same options, and a file in more than one
+    /// is taken from the last one it is in
+    #[arg(
+        long,
+        value_name = "DIR",
+        num_args = 1..,
+        requires = "into",
+        conflicts_with_all = [
+            "dir", "library", "from_playlist", "index_path", "serve", "connect", "rpc", "query",
+            "lookup", "dump", "dump_terms",
+        ]
+    )]
+    merge: Vec<Utf8PathBuf>,
+
+    /// the dir `--merge` saves the combined index in, which is created the first time and added
+    /// to after that
+    #[arg(long, value_name = "DIR", requires = "merge")]
+    into: Option<Utf8PathBuf>,
+
+    /// with `--query`, print only how many songs match it
+    #[arg(long, requires = "query", conflicts_with = "connect")]
+    count: bool,
+
+    /// name files under a symlinked dir through the dir as passed rather than what it points to,
+    /// symlinks below it are still resolved either way
+    #[arg(long)]
+    no_follow_root_symlink: bool,
+
+    /// resolve every path to its canonical form, `--canonicalize false` keeps paths as they were
+    /// walked instead, which is faster but lets a file reached by several paths be indexed twice
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set, value_name = "BOOL")]
+    canonicalize: bool,
+
+    /// also index files and dirs whose names start with `.`, like `.Trash`, these are skipped by
+    /// default. AppleDouble `._` files are skipped either way
+    #[arg(long)]
+    hidden: bool,
+
+    /// also index the audio inside `.zip` archives, under paths like `zip:///music/a.zip!01.flac`.
+    /// every file is extracted to read it, and again to open it
+    #[arg(long)]
+    index_archives: bool,
+
+    /// longest a tag value may be in bytes, longer ones (like images pasted into a comment) are
+    /// cut short
+    #[arg(long, default_value_t = AudioFile::DEFAULT_MAX_TAG_LEN, value_name = "BYTES")]
+    max_tag_len: usize,
+
+    /// merge the tags of `song.json` into `song.wav` and so on, a flat json object of tag names to
+    /// values. `tags` has embedded tags win where both have one, `sidecar` has the json win
+    #[arg(
+        long,
+        value_name = "PRECEDENCE",
+        value_parser = clap::builder::PossibleValuesParser::new(Sidecar::NAMES)
+            .map(|s| s.parse::<Sidecar>().expect("only known names get this far")),
+    )]
+    sidecars: Option<Sidecar>,
+
+    /// keep tag values' doubled spaces, tabs and newlines as they are instead of making each run
+    /// of them one space, which otherwise keeps `Pink  Floyd` from being a second artist
+    #[arg(long)]
+    no_normalize_whitespace: bool,
+
+    /// which year a reissue gets, `release` for when that copy came out or `original` for when
+    /// the music did, as read from its originaldate or originalyear tag
+    #[arg(
+        long,
+        default_value = "release",
+        value_parser = clap::builder::PossibleValuesParser::new(YearSource::NAMES)
+            .map(|s| s.parse::<YearSource>().expect("only known names get this far")),
+    )]
+    year_source: YearSource,
+
+    /// read at most this many files at once, lower values can be faster on spinning disks where
+    /// reading many files at once means seeking between all of them. unlimited by default
+    #[arg(long, value_name = "N")]
+    probe_concurrency: Option<NonZeroUsize>,
+
+    /// leave songs shorter than this out of the index, such as interludes and jingles. takes
+    /// seconds, `m:ss`, or hours, minutes and seconds as in `1h2m` or `90s`. chapters go by the length of the file they are in
+    #[arg(long, value_name = "DURATION", value_parser = duration_arg)]
+    min_duration: Option<u64>,
+
+    /// leave songs longer than this out of the index, in the same format as `--min-duration`
+    #[arg(long, value_name = "DURATION", value_parser = duration_arg)]
+    max_duration: Option<u64>,
+
+    /// leave songs with this genre out of the index, ignoring case. may be passed more than once
+    #[arg(long, value_name = "GENRE")]
+    exclude_genre: Vec<String>,
+
+    /// leave songs by this artist (or album artist) out of the index, ignoring case. may be passed
+    /// more than once
+    #[arg(long, value_name = "ARTIST")]
+    exclude_artist: Vec<String>,
+
+    /// log more about what is happening, `-vv` logs every file looked at. `RUST_LOG` overrides
+    /// this
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+}
 
-    /// unlike AudioFile which prefers artist over album_artist, we prefer album_artist here
-    artist_name: String,
+const EXIT_CODES: &str = "\
+Exit codes:
+  0  success, or with --query at least one result
+  1  --query found no results
+  2  invalid arguments
+  3  reading, indexing, searching or a connection failed
+  4  ffmpeg could not be loaded or could not read a test wav";
+
+/// what stops musicsearch early, each kind exits with its own code so scripts can tell them apart
+#[derive(Debug)]
+enum Error {
+    /// reading files, the index, a search or a connection failed
+    Index(String),
+    /// ffmpeg could not be initialized or could not read even a plain wav file
+    Ffmpeg(String),
+}
 
-    year: Option<u32>,
+impl Error {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            Self::Index(_) => ExitCode::from(3),
+            Self::Ffmpeg(_) => ExitCode::from(4),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Index(e) | Self::Ffmpeg(e) => f.write_str(e),
+        }
+    }
 }
 
-#[derive(Default, Debug)]
-struct AudioFile {
-    /// displayed (but only index the filename)
-    file_path: Utf8PathBuf,
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Index(e.to_string())
+    }
+}
 
-    /// normally the same as artist, should be indexed but only displayed as fallback
-    album_artist: Option<String>,
-    artist: Option<String>,
-    album: Option<String>,
-    title: Option<String>,
-    /// if a part of an album this is the track number within that album
-    track: Option<u64>,
-    date: Option<String>,
+impl From<tantivy::TantivyError> for Error {
+    fn from(e: tantivy::TantivyError) -> Self {
+        Self::Index(e.to_string())
+    }
+}
 
-    /// may be parsed off of date if it exists, or via the explicit year key
-    year: Option<u32>,
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Match {
+    Any,
+    All,
+}
 
-    /// keys are first lowercased
-    extras: HashMap<String, String>,
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum GroupBy {
+    Album,
 }
 
-impl AudioFile {
-    fn new(path: Utf8PathBuf) -> Self {
-        let mut this = Self::default();
+/// what goes between results at the prompt
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Separator {
+    None,
+    /// an empty line
+    Blank,
+    /// a dim line across the terminal
+    Rule,
+}
 
-        this.file_path = path;
+fn query_parser(args: &Args, index: &MusicIndex) -> QueryParser {
+    let mut qp = if args.field.is_empty() {
+        index.query_parser()
+    } else {
+        index.query_parser_for(&args.field)
+    };
 
-        this
+    if let Match::All = args.match_mode {
+        qp.set_conjunction_by_default();
     }
 
-    fn place(&mut self, key: impl Into<String> + AsRef<str>, value: impl Into<String>) {
-        let k = key.as_ref().to_lowercase();
-        let value = value.into();
-
-        match &*k {
-            "album_artist" => self.album_artist = Some(value),
-            "artist" => self.artist = Some(value),
-            "album" => self.album = Some(value),
-            "title" => self.title = Some(value),
-            "track" => {
-                let i = value.split_once('/').map_or(&*value, |(n, _total)| n);
+    qp
+}
 
-                if let Ok(n) = i.parse() {
-                    self.track = Some(n);
-                }
-            }
-            "date" => self.date = Some(value),
+/// a tenth of a second of silence as 8 bit mono wav, which every ffmpeg build can read
+fn probe_wav() -> Vec<u8> {
+    const SAMPLES: u32 = 800;
+
+    let mut wav = Vec::with_capacity(44 + SAMPLES as usize);
+
+    wav.extend(b"RIFF");
+    wav.extend((36 + SAMPLES).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    // pcm, 1 channel, 8000hz, 8000 bytes a second, 1 byte a sample, 8 bits a sample
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(8000u32.to_le_bytes());
+    wav.extend(8000u32.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(8u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend(SAMPLES.to_le_bytes());
+    wav.resize(wav.len() + SAMPLES as usize, 0x80);
+
+    wav
+}
 
-            _ => {
-                self.extras.insert(k, value);
-            }
+/// ffmpeg not working shows up as every file failing to parse, which looks just like having no
+/// music, so it gets checked once before indexing anything by reading a file it has to be able to
+pub(crate) fn check_ffmpeg() -> Result<(), String> {
+    ffmpeg_next::init().map_err(|e| format!("could not initialize ffmpeg: {e}"))?;
+
+    // a fresh dir of its own, so nothing else can be waiting at the probe's path
+    let written = ScratchDir::new().and_then(|dir| {
+        let probe = dir.path().join("probe.wav");
+
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)?
+            .write_all(&probe_wav())?;
+
+        Ok((dir, probe))
+    });
+
+    let (_dir, probe) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            // nothing to read with, the files themselves will have to tell
+            debug!("could not write a probe file, not checking ffmpeg: {e}");
+            return Ok(());
+        }
+    };
+
+    let read = ffmpeg_next::format::input(&probe);
+
+    match read {
+        Ok(input)
+            if input
+                .streams()
+                .best(ffmpeg_next::media::Type::Audio)
+                .is_some() =>
+        {
+            Ok(())
         }
+        Ok(_) => Err("ffmpeg found no audio in a wav file, so it would not in any music".into()),
+        Err(e) => Err(format!(
+            "ffmpeg could not read a wav file, so it would not read any music either: {e}"
+        )),
     }
+}
 
-    fn from_kv_and_path<'a>(
-        path: impl Into<Utf8PathBuf>,
-        kv: impl Iterator<Item = (&'a str, &'a str)>,
-    ) -> Self {
-        let mut this = Self::new(path.into());
+/// what an excluded count in a message was excluded by
+const EXCLUDE_FLAGS: &str = "--exclude-genre, --exclude-artist, --min-duration or --max-duration";
 
-        for (k, v) in kv {
-            this.place(k, v);
+fn library_arg(value: &str) -> Result<(String, Utf8PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, dir)) if !name.is_empty() && !dir.is_empty() => {
+            Ok((name.to_owned(), dir.into()))
         }
+        _ => Err(format!("{value:?} is not NAME=DIR")),
+    }
+}
+
+/// the library files under dir are marked with, the name it was given by `--library` or else the
+/// name of the dir itself
+fn library_name(args: &Args, dir: &Utf8Path) -> Option<String> {
+    if let Some((name, _)) = args.library.iter().find(|(_, d)| d == dir) {
+        return Some(name.clone());
+    }
 
-        this
+    // `.` and `..` have no name of their own, but what they point to does
+    match dir.file_name() {
+        Some(name) => Some(name.to_owned()),
+        None => dir.canonicalize_utf8().ok()?.file_name().map(str::to_owned),
     }
+}
 
-    fn tantivy_store(&self, scm: &HardSchema) -> TantivyDocument {
-        let mut doc = TantivyDocument::new();
+fn sort_arg(value: &str) -> Result<(SortKey, Order), String> {
+    let (key, order) = match value.split_once(':') {
+        Some((key, order)) => (key, Some(order)),
+        None => (value, None),
+    };
 
-        doc.add_text(scm.path, &self.file_path);
+    let key = key
+        .parse::<SortKey>()
+        .map_err(|e| format!("{e}, expected one of {}", SortKey::NAMES.join(", ")))?;
 
-        if let Some(artist) = self.artist.as_ref().or(self.album_artist.as_ref()) {
-            doc.add_text(scm.artist, artist);
-        }
+    let order = match order {
+        None => key.default_order(),
+        Some("asc") => Order::Asc,
+        Some("desc") => Order::Desc,
+        Some(order) => return Err(format!("unknown order {order:?}, expected asc or desc")),
+    };
 
-        if let Some(album) = &self.album {
-            doc.add_text(scm.album, album);
-        }
+    Ok((key, order))
+}
 
-        if let Some(title) = &self.title {
-            doc.add_text(scm.title, title);
-        }
+fn ngram_stop_arg(value: &str) -> Result<String, String> {
+    if value.chars().count() != 3 {
+        return Err(format!(
+            "{value:?} is not 3 characters, the length every indexed ngram is"
+        ));
+    }
 
-        if let Some(track) = self.track {
-            doc.add_u64(scm.track, track);
-        }
+    Ok(value.to_lowercase())
+}
+
+fn promote_tag_arg(value: &str) -> Result<String, String> {
+    let key = value.to_lowercase();
 
-        if let Some(date) = &self.date {
-            doc.add_text(scm.date, date);
+    if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!(
+            "{value:?} can not be a field name, only letters, digits and _ can"
+        ));
+    }
+
+    if classify_tag(&key) != TagField::Extra || HardSchema::is_field_name(&key) {
+        return Err(format!("{value:?} already has a field"));
+    }
+
+    Ok(key)
+}
+
+fn duration_arg(value: &str) -> Result<u64, String> {
+    parse_duration(value).ok_or_else(|| format!("{value:?} is not seconds, m:ss or like 1h2m30s"))
+}
+
+/// whether a parsed file matches any `--exclude-genre` or `--exclude-artist`, or is outside of
+/// `--min-duration` and `--max-duration`. files whose duration is unknown are kept
+fn is_excluded(args: &Args, file: &AudioFile) -> bool {
+    // a chapter is part of a song that is kept or not as a whole, only the tags are its own
+    let duration = match file.start {
+        Some(_) => file.file_duration,
+        None => file.duration,
+    };
+
+    if let Some(duration) = duration {
+        if args.min_duration.is_some_and(|min| duration < min)
+            || args.max_duration.is_some_and(|max| duration > max)
+        {
+            return true;
         }
+    }
 
-        doc.add_text(
-            scm.extras,
-            self.extras
-                .values()
-                .map(|s| &**s)
-                .collect::<Vec<&str>>()
-                .join(" "),
-        );
+    let matches = |excludes: &[String], value: &str| {
+        let value = value.to_lowercase();
+        excludes.iter().any(|e| e.to_lowercase() == value)
+    };
 
-        doc.add_text(scm.item_type, "song");
+    file.genre.iter().any(|g| matches(&args.exclude_genre, g))
+        || [&file.artist, &file.album_artist]
+            .into_iter()
+            .flatten()
+            .any(|a| matches(&args.exclude_artist, a))
+}
 
-        doc
+fn scan_options(args: &Args) -> ScanOptions {
+    ScanOptions {
+        chapters: args.chapters,
+        keep_root_symlink: args.no_follow_root_symlink,
+        keep_walked_paths: !args.canonicalize,
+        max_tag_len: args.max_tag_len,
+        hidden: args.hidden,
+        sidecars: args.sidecars,
+        probe_concurrency: args.probe_concurrency,
+        normalize_whitespace: !args.no_normalize_whitespace,
+        host: gethostname::gethostname().into_string().ok(),
+        year_source: args.year_source,
+        library: None,
+        archives: args.index_archives,
+        indexed: None,
     }
+}
 
-    fn store_fieldvalue(&mut self, scm: &HardSchema, fv: &FieldValue) {
-        let f = &fv.field;
+/// what a saved index held before it is updated, for only reading what changed since
+struct Previous {
+    /// the mtime every file in it was read at
+    indexed: HashMap<Utf8PathBuf, Option<u64>>,
+    /// the files it was last updated from but passed over, see [`Skipped`]
+    skipped: HashMap<Utf8PathBuf, u64>,
+}
 
-        fn must_string(v: &OwnedValue) -> String {
-            let OwnedValue::Str(s) = v else {
-                unreachable!("this field must be a string")
-            };
+impl Previous {
+    fn read(args: &Args, index: &MusicIndex, searcher: &Searcher) -> Result<Self, Error> {
+        let read = || {
+            let skipped = index.skipped()?;
+
+            Ok(Self {
+                indexed: index.modified_times(searcher)?,
+                // what other flags excluded may not be excluded any more
+                skipped: if skipped.excluded_by == excluded_by(args) {
+                    skipped.files
+                } else {
+                    HashMap::new()
+                },
+            })
+        };
+
+        read().map_err(|e: TantivyError| Error::Index(format!("could not read the index: {e}")))
+    }
 
-            s.to_owned()
+    /// the mtimes of files already indexed or passed over, as [`ScanOptions::indexed`] takes them,
+    /// each archive's entries listed once under the archive
+    fn unchanged_since(&self) -> HashMap<Utf8PathBuf, u64> {
+        self.indexed
+            .iter()
+            .filter_map(|(path, modified)| {
+                let path = split_archive_path(path).map_or(&**path, |(archive, _)| archive);
+
+                Some((path.to_owned(), (*modified)?))
+            })
+            .chain(
+                self.skipped
+                    .iter()
+                    .map(|(path, &modified)| (path.clone(), modified)),
+            )
+            .collect()
+    }
+}
+
+/// the exclude flags as [`Skipped::excluded_by`] records them
+fn excluded_by(args: &Args) -> String {
+    format!(
+        "{:?}",
+        (
+            &args.exclude_genre,
+            &args.exclude_artist,
+            args.min_duration,
+            args.max_duration
+        )
+    )
+}
+
+/// what to record as skipped once added was added on top of previous: what previous passed over
+/// that is still on disk at the same mtime, so was passed over again without being read, and what
+/// was passed over this time
+fn skipped_after(args: &Args, previous: Option<&Previous>, added: &Added) -> Skipped {
+    let unchanged = |path: &Utf8Path, modified: u64| {
+        path.metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .is_some_and(|t| t.as_secs() == modified)
+    };
+
+    let mut files: HashMap<_, _> = previous
+        .into_iter()
+        .flat_map(|previous| &previous.skipped)
+        .filter(|(path, &modified)| unchanged(path, modified))
+        .map(|(path, &modified)| (path.clone(), modified))
+        .collect();
+
+    files.extend(
+        added
+            .skipped
+            .iter()
+            .map(|(path, &modified)| (path.clone(), modified)),
+    );
+
+    Skipped {
+        excluded_by: excluded_by(args),
+        files,
+    }
+}
+
+/// deletes every file in previous whose path is gone from disk, or that was in an archive which is
+/// gone or no longer holds it, returning how many. these go before anything is added again, so a
+/// changed archive does not keep the files taken out of it
+fn remove_missing(index: &MusicIndex, writer: &IndexWriter, previous: &Previous) -> usize {
+    // each archive is listed once however many of its files were indexed, a gone one holds
+    // nothing and one that can not be listed is left as indexed, reading it again would fail too
+    let mut listings: HashMap<&Utf8Path, Option<HashSet<String>>> = HashMap::new();
+
+    previous
+        .indexed
+        .keys()
+        .filter(|path| match split_archive_path(path) {
+            Some((archive, entry)) => {
+                let listing =
+                    listings
+                        .entry(archive)
+                        .or_insert_with(|| match audio_entries(archive) {
+                            Ok(entries) => Some(entries.into_iter().collect()),
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => Some(HashSet::new()),
+                            Err(e) => {
+                                warn!("could not list {archive}: {e}");
+                                None
+                            }
+                        });
+
+                listing.as_ref().is_some_and(|l| !l.contains(entry))
+            }
+            None => !path.exists(),
+        })
+        .inspect(|path| {
+            trace!("removing {path}, it no longer exists");
+            index.delete_path(writer, path.as_str());
+        })
+        .count()
+}
+
+/// what [`add_files`] added, and what it left out
+#[derive(Default)]
+struct Added {
+    songs: usize,
+    chapters: usize,
+    /// files left out by [`EXCLUDE_FLAGS`]
+    excluded: usize,
+    /// files excluded or not readable with the mtime they had, under their archive's path for
+    /// files in one, so the next update can pass over them without reading them
+    skipped: HashMap<Utf8PathBuf, u64>,
+}
+
+impl Added {
+    fn and(mut self, other: Self) -> Self {
+        self.songs += other.songs;
+        self.chapters += other.chapters;
+        self.excluded += other.excluded;
+        self.skipped.extend(other.skipped);
+        self
+    }
+
+    /// one file passed over, the path and mtime it was read with
+    fn skipped(path: &Utf8Path, modified: Option<u64>) -> Self {
+        let path = split_archive_path(path).map_or(path, |(archive, _)| archive);
+
+        Self {
+            skipped: modified
+                .map(|modified| (path.to_owned(), modified))
+                .into_iter()
+                .collect(),
+            ..Self::default()
         }
+    }
+}
 
-        fn must_u64(v: &OwnedValue) -> u64 {
-            let &OwnedValue::U64(v) = v else {
-                unreachable!("this field must be a u64")
+/// adds every read file to the index without committing, returning what was added and what was
+/// left out. with replace whatever was indexed under a file's path before is deleted as it is
+/// added
+fn add_files(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &IndexWriter,
+    files: impl ParallelIterator<Item = io::Result<Vec<AudioFile>>>,
+    progress: &ProgressBar,
+    replace: bool,
+) -> Result<Added, Error> {
+    files
+        // ticked per walked file, which is what the total counts however many songs one holds
+        .inspect(|_| progress.inc(1))
+        .flat_map_iter(|res| match res {
+            Ok(files) => files.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        })
+        .map(|v| {
+            let f = match v {
+                Ok(f) => f,
+                // files that are not audio by their extension, or are indexed as they are already,
+                // are skipped even when strict
+                Err(e)
+                    if args.strict
+                        && !matches!(
+                            e.kind(),
+                            io::ErrorKind::Unsupported | io::ErrorKind::AlreadyExists
+                        ) =>
+                {
+                    return Err(Error::Index(e.to_string()));
+                }
+                Err(e) => {
+                    return Ok(Unreadable::of(&e).map_or_else(Added::default, |file| {
+                        Added::skipped(&file.path, file.modified)
+                    }));
+                }
             };
 
-            v
-        }
+            let is_chapter = f.start.is_some();
+
+            // a file is read before its chapters, so this never deletes one of them. it goes
+            // before excluding, a file edited to be excluded is still not what was indexed
+            if replace && !is_chapter {
+                index.delete_path(writer, f.file_path.as_str());
+            }
+
+            if is_excluded(args, &f) {
+                trace!("excluding {}", f.file_path);
+
+                return Ok(match is_chapter {
+                    true => Added::default(),
+                    false => Added {
+                        excluded: 1,
+                        ..Added::skipped(&f.file_path, f.modified)
+                    },
+                });
+            }
+
+            // add_document is safe to call from every thread at once, documents are queued to
+            // tantivy's own indexing threads. a file that fails is skipped like one that could
+            // not be read, the commit after reports if the writer itself is broken
+            if let Err(e) = writer.add_document(f.tantivy_store(index.schema())) {
+                let e = format!("could not index {}: {e}", f.file_path);
+
+                if args.strict {
+                    return Err(Error::Index(e));
+                }
+
+                warn!("{e}");
+                return Ok(Added::default());
+            }
+
+            Ok(Added {
+                songs: usize::from(!is_chapter),
+                chapters: usize::from(is_chapter),
+                ..Added::default()
+            })
+        })
+        .try_reduce(Added::default, |a, b| Ok(a.and(b)))
+}
+
+/// walks dirs adding every song found to the index without committing, each marked with the
+/// library paired with its dir, returning how many songs and chapters were added and how many
+/// files were excluded. previous is what the index already holds, files in it (or passed over by
+/// it) still at the mtime they were read with are skipped and changed ones replace what was
+/// indexed for them
+fn index_dirs<'a>(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &IndexWriter,
+    dirs: impl IntoIterator<Item = (&'a Utf8Path, Option<String>)>,
+    previous: Option<&Previous>,
+) -> Result<Added, Error> {
+    let opts = ScanOptions {
+        indexed: previous.map(|previous| Arc::new(previous.unchanged_since())),
+        ..scan_options(args)
+    };
+    let replace = previous.is_some_and(|previous| !previous.indexed.is_empty());
+
+    let dirs: Vec<_> = dirs.into_iter().collect();
+
+    // a quick walk without ffmpeg first, so the bar has a total and an eta
+    let total = dirs.iter().map(|(dir, _)| count_files(dir, &opts)).sum();
+    let progress = progress_bar(total);
+
+    let counts = dirs
+        .into_iter()
+        .try_fold(Added::default(), |added, (dir, library)| {
+            debug!("indexing {dir}");
+
+            let opts = ScanOptions {
+                library,
+                ..opts.clone()
+            };
+
+            let files = recursive_find_audiofiles(dir, &opts);
+
+            Ok(added.and(add_files(args, index, writer, files, &progress, replace)?))
+        });
+
+    // cleared on errors too, so the error is not printed under a half finished bar
+    drop(progress);
 
-        #[deny(unused_variables)]
-        let HardSchema {
-            path,
-            artist,
-            album,
-            title,
-            track,
-            date,
-            extras,
-            item_type,
-        } = scm;
-
-        _ = (extras, item_type);
-
-        match f {
-            _ if f == path => self.file_path = must_string(&fv.value).into(),
-            _ if f == artist => self.artist = Some(must_string(&fv.value)),
-            _ if f == album => self.album = Some(must_string(&fv.value)),
-            _ if f == title => self.title = Some(must_string(&fv.value)),
-            _ if f == track => self.track = Some(must_u64(&fv.value)),
-            _ if f == date => self.date = Some(must_string(&fv.value)),
-
-            _ => (),
+    counts
+}
+
+/// deletes what the index holds under each `--reindex-path` and reads it all again without
+/// committing, counted the same way as [`index_dirs`]. previous is what the index held before, to
+/// find the library each dir was indexed as
+fn reindex_paths(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &IndexWriter,
+    searcher: &Searcher,
+    previous: Option<&Previous>,
+) -> Result<Added, Error> {
+    let opts = scan_options(args);
+
+    let mut dirs = Vec::new();
+
+    for dir in &args.reindex_path {
+        if !dir.is_dir() {
+            return Err(Error::Index(format!(
+                "could not reindex {dir}, not a directory"
+            )));
         }
+
+        let indexed = indexed_dir(dir, &opts)
+            .map_err(|e| Error::Index(format!("could not reindex {dir}: {e}")))?;
+
+        // any file under it says what library it was, a dir that was never indexed is named as
+        // if it were passed
+        let library = previous
+            .into_iter()
+            .flat_map(|previous| previous.indexed.keys())
+            .find(|path| {
+                split_archive_path(path)
+                    .map_or(&***path, |(archive, _)| archive)
+                    .starts_with(&indexed)
+            })
+            .and_then(|path| index.lookup(searcher, path.as_str()).ok()?.pop())
+            .map_or_else(|| library_name(args, dir), |file| file.library);
+
+        index
+            .delete_under(writer, &indexed)
+            .map_err(|e| Error::Index(format!("could not clear {dir} from the index: {e}")))?;
+
+        dirs.push((&**dir, library));
     }
 
-    fn tantivy_recall(scm: &HardSchema, doc: &TantivyDocument) -> Self {
-        let mut s = Self::new(Utf8PathBuf::new());
+    // everything under them was deleted, so nothing is skipped or replaced
+    index_dirs(args, index, writer, dirs, None)
+}
 
-        for itm in doc.field_values() {
-            s.store_fieldvalue(scm, itm);
-        }
+/// adds the tracks of every `--from-playlist` to the index without committing, counted the same
+/// way as [`index_dirs`]. every track is read again, replacing what previous has for it
+fn index_playlists(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &IndexWriter,
+    previous: Option<&Previous>,
+) -> Result<Added, Error> {
+    let opts = scan_options(args);
+    let replace = previous.is_some_and(|previous| !previous.indexed.is_empty());
 
-        s
+    let mut playlists = Vec::new();
+
+    for playlist in &args.from_playlist {
+        let entries = read_playlist(playlist)
+            .map_err(|e| Error::Index(format!("could not read playlist {playlist}: {e}")))?;
+
+        playlists.push((playlist, entries));
     }
+
+    let progress = progress_bar(playlists.iter().map(|(_, entries)| entries.len()).sum());
+
+    let counts = playlists
+        .into_iter()
+        .try_fold(Added::default(), |added, (playlist, entries)| {
+            debug!("indexing {} tracks from {playlist}", entries.len());
+
+            let files = playlist_audiofiles(entries, &opts);
+
+            Ok(added.and(add_files(args, index, writer, files, &progress, replace)?))
+        });
+
+    drop(progress);
+
+    counts
 }
 
-impl Display for AudioFile {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // file name must exist to be a valid AudioFile
-        let fname = self.file_path.file_name().unwrap();
+/// the bar being drawn while files are read, if one is. logs are written with it cleared off the
+/// terminal and drawn again after, so a warning never lands in the middle of it
+static PROGRESS: Mutex<Option<ProgressBar>> = Mutex::new(None);
 
-        write!(f, "\x1b[37m{fname}")?;
+/// a bar on stderr counting files read out of total, which stays hidden when stderr is not a
+/// terminal. it is cleared once dropped
+fn progress_bar(total: usize) -> Progress {
+    let style =
+        ProgressStyle::with_template("{wide_bar} {pos}/{len} files ({percent}%, {eta} left)")
+            .expect("the template is valid");
 
-        if let Some(title) = &self.title {
-            write!(f, ": \x1b[92m{title}")?;
-        }
+    let bar = ProgressBar::new(total as u64).with_style(style);
+    *PROGRESS.lock().unwrap_or_else(PoisonError::into_inner) = Some(bar.clone());
 
-        if let Some(artist) = self.artist.as_ref().or(self.album_artist.as_ref()) {
-            write!(f, " - \x1b[92m{artist}")?;
-        }
+    Progress(bar)
+}
 
-        if let Some(album) = &self.album {
-            write!(f, " \x1b[37m- \x1b[94m{album}")?;
-        }
+/// the bar [`progress_bar`] shows, kept in [`PROGRESS`] for as long as it lives
+struct Progress(ProgressBar);
 
-        if let Some(track) = self.track {
-            write!(f, "\x1b[94m #{track}")?;
-        }
+impl std::ops::Deref for Progress {
+    type Target = ProgressBar;
+
+    fn deref(&self) -> &ProgressBar {
+        &self.0
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+        *PROGRESS.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// stderr as logs are written to it, around whatever bar [`PROGRESS`] holds
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
 
-        if let Some(date) = &self.date {
-            write!(f, "\x1b[32m ({date})")?;
+    // a whole log line comes in one call, so the bar is cleared once for it
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match &*PROGRESS.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(bar) => bar.suspend(|| io::stderr().write_all(buf)),
+            None => io::stderr().write_all(buf),
         }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
 
-        write!(f, "\x1b[0m")?;
+/// the file descriptor `--output-fd` names, which whatever started musicsearch has to have opened.
+/// the file is a duplicate of it, so closing the file leaves fd itself open
+#[cfg(unix)]
+fn output_fd(fd: i32) -> Result<fs::File, Error> {
+    use std::os::fd::FromRawFd;
 
-        Ok(())
+    // SAFETY: dup only looks fd up, and fails if it is not open
+    let dup = unsafe { libc::dup(fd) };
+
+    if dup == -1 {
+        return Err(Error::Index(format!(
+            "could not write to file descriptor {fd}: {}",
+            io::Error::last_os_error()
+        )));
     }
+
+    // SAFETY: dup was just opened, and nothing else owns it
+    Ok(unsafe { fs::File::from_raw_fd(dup) })
 }
 
-struct HardSchema {
-    path: Field,
-    artist: Field,
-    album: Field,
-    title: Field,
-    track: Field,
-    date: Field,
-    extras: Field,
-    item_type: Field,
+#[cfg(not(unix))]
+fn output_fd(_: i32) -> Result<fs::File, Error> {
+    Err(Error::Index("--output-fd is only available on unix".into()))
 }
 
-impl HardSchema {
-    const PATH: &'static str = "path";
-    const ARTIST: &'static str = "artist";
-    const ALBUM: &'static str = "album";
-    const TITLE: &'static str = "title";
-    const TRACK: &'static str = "track";
-    const DATE: &'static str = "date";
-    const EXTRAS: &'static str = "extras";
-    const ITEM_TYPE: &'static str = "type";
+/// whether anything passed has files read from it, otherwise ffmpeg is not needed until `:index`
+fn reads_files(args: &Args) -> bool {
+    !(args.dir.is_empty()
+        && args.library.is_empty()
+        && args.from_playlist.is_empty()
+        && args.reindex_path.is_empty())
+}
 
-    fn schema() -> (Schema, Self) {
-        let mut schema = Schema::builder();
+/// the index with its writer, which is kept so the ui can add directories to it later. a saved
+/// index that is only searched has none, so another process can write to it meanwhile
+fn build_index(args: &Args) -> Result<(MusicIndex, Option<IndexWriter>), Error> {
+    let nothing_passed = !reads_files(args);
 
-        let text = TEXT.set_indexing_options(
-            TextFieldIndexing::default()
-                .set_tokenizer("ngram3")
-                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+    // a saved index has everything it was built from already
+    if nothing_passed && args.index_path.is_none() {
+        warn!("no directories or playlists passed");
+    }
+
+    let opts = SchemaOptions {
+        stored_only: args.stored_only.clone(),
+        keyword_fields: args
+            .keyword_fields
+            .iter()
+            .filter(|f| *f != "none")
+            .cloned()
+            .collect(),
+        ngram_prefix_only: args.ngram_prefix_only,
+        ngram_stop: args.ngram_stop.clone(),
+        no_extras: args.no_extras,
+        promoted_tags: args.promote_tag.clone(),
+    };
+
+    let (index, created) = match &args.index_path {
+        Some(dir) => MusicIndex::open_or_create_in_dir(dir, &opts).map_err(|e| match e {
+            TantivyError::SchemaError(why) => Error::Index(format!(
+                "could not open the index in {dir}, {why}. pass the options it was built with \
+                 or remove it to build it again"
+            )),
+            e => Error::Index(format!("could not open the index in {dir}: {e}")),
+        })?,
+        None => (MusicIndex::create_in_ram(&opts), true),
+    };
+
+    let reader = index
+        .reader()
+        .map_err(|e| Error::Index(format!("could not read the index: {e}")))?;
+
+    // only one process can write to an index, so one only searching it leaves the writer for
+    // `:index` to take, or for `--serve-index` in another process
+    if !created && nothing_passed && !args.reindex {
+        info!(
+            "{} entries in the saved index",
+            reader.searcher().num_docs()
         );
-        let text_stored = text.clone() | STORED;
-
-        schema.add_text_field(HardSchema::PATH, text_stored.clone());
-        schema.add_text_field(HardSchema::ARTIST, text_stored.clone());
-        schema.add_text_field(HardSchema::ALBUM, text_stored.clone());
-        schema.add_text_field(HardSchema::TITLE, text_stored.clone());
-        schema.add_u64_field(HardSchema::TRACK, INDEXED | STORED);
-        schema.add_text_field(HardSchema::DATE, text_stored.clone());
-        schema.add_text_field(HardSchema::EXTRAS, text);
-        schema.add_text_field(HardSchema::ITEM_TYPE, text_stored.clone());
-
-        let scm = schema.build();
-
-        let hard = Self::from_schema(&scm);
-
-        (scm, hard)
-    }
-
-    fn all(&self) -> Vec<Field> {
-        vec![
-            self.path,
-            self.artist,
-            self.album,
-            self.title,
-            self.track,
-            self.date,
-            self.extras,
-            self.item_type,
-        ]
+
+        return Ok((index, None));
     }
 
-    fn from_schema(schema: &Schema) -> Self {
-        // none of these will panic when used on the schema generated by tantivy_schema
-        Self {
-            path: schema.get_field(HardSchema::PATH).unwrap(),
-            artist: schema.get_field(HardSchema::ARTIST).unwrap(),
-            album: schema.get_field(HardSchema::ALBUM).unwrap(),
-            title: schema.get_field(HardSchema::TITLE).unwrap(),
-            track: schema.get_field(HardSchema::TRACK).unwrap(),
-            date: schema.get_field(HardSchema::DATE).unwrap(),
-            extras: schema.get_field(HardSchema::EXTRAS).unwrap(),
-            item_type: schema.get_field(HardSchema::ITEM_TYPE).unwrap(),
+    let mut writer = match index.writer() {
+        Ok(writer) => writer,
+        Err(TantivyError::LockFailure(..)) => {
+            return Err(Error::Index(
+                "could not write to the index, another musicsearch is writing to it. pass no \
+                 dirs or playlists to only search it"
+                    .into(),
+            ));
+        }
+        Err(e) => {
+            return Err(Error::Index(format!(
+                "could not create the index writer: {e}"
+            )))
         }
+    };
+
+    // a saved index is only updated with what changed, unless it is to be read again from scratch
+    let previous = if created || args.reindex {
+        None
+    } else {
+        Some(Previous::read(args, &index, &reader.searcher())?)
+    };
+
+    if args.reindex && !created {
+        writer
+            .delete_all_documents()
+            .map_err(|e| Error::Index(format!("could not clear the index: {e}")))?;
     }
-}
 
-fn recursive_find_audiofiles(
-    subdir: &Utf8Path,
-) -> impl ParallelIterator<Item = io::Result<AudioFile>> {
-    WalkDir::new(subdir)
-        .follow_links(true)
-        .into_iter()
-        .par_bridge()
-        .filter(|p| p.as_ref().map_or(true, |f| f.file_type().is_file()))
-        .map(|res| {
-            let file = res?;
+    if let Some(previous) = &previous {
+        let removed = remove_missing(&index, &writer, previous);
 
-            let path = Utf8PathBuf::try_from(file.into_path()).map_err(|e| e.into_io_error())?;
+        if removed > 0 {
+            info!("{removed} files no longer exist, removed them from the index");
+        }
+    }
 
-            let Some(ext) = path.extension() else {
-                return Err(io::Error::other("not an audio file"));
-            };
+    let start = Instant::now();
+
+    // before the other dirs, which skip what was just read again as unchanged
+    let added = reindex_paths(args, &index, &writer, &reader.searcher(), previous.as_ref())?
+        .and(index_dirs(
+            args,
+            &index,
+            &writer,
+            args.dir
+                .iter()
+                .chain(args.library.iter().map(|(_, dir)| dir))
+                .map(|dir| (&**dir, library_name(args, dir))),
+            previous.as_ref(),
+        )?)
+        .and(index_playlists(args, &index, &writer, previous.as_ref())?);
+
+    debug!("read every file in {:?}", start.elapsed());
+
+    let commit_start = Instant::now();
+    index
+        .commit_skipped(&mut writer, skipped_after(args, previous.as_ref(), &added))
+        .map_err(|e| Error::Index(format!("could not commit the index: {e}")))?;
+    debug!("committed in {:?}", commit_start.elapsed());
+
+    let Added {
+        songs,
+        chapters,
+        excluded,
+        ..
+    } = added;
+
+    if previous.is_some() {
+        let entries = reader
+            .reload()
+            .map(|_| reader.searcher().num_docs())
+            .map_err(|e| Error::Index(format!("could not read the index: {e}")))?;
+
+        info!("read {songs} new or changed songs, {entries} entries in index");
+    } else if args.chapters {
+        info!("{songs} songs and {chapters} chapters in index");
+    } else {
+        info!("{songs} songs in index");
+    }
+
+    if excluded > 0 {
+        info!("{excluded} excluded by {EXCLUDE_FLAGS}");
+    }
+
+    Ok((index, Some(writer)))
+}
 
-            if !AUDIO_EXT.contains(ext) {
-                return Err(io::Error::other("not an audio file"));
+/// walks the passed dirs again every interval for good, committing whatever changed in them so
+/// that searches of the same `--index-path` in other processes pick it up. a path written to stdin
+/// (one a line, as by a tag editor's save hook) is read again right away on its own, without
+/// walking anything else. returns only when reading or committing the index fails
+fn serve_index(
+    args: &Args,
+    index: &MusicIndex,
+    mut writer: IndexWriter,
+    every: u64,
+) -> Result<(), Error> {
+    let reader = index
+        .reader()
+        .map_err(|e| Error::Index(format!("could not read the index: {e}")))?;
+
+    let (send, changed) = mpsc::channel();
+
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            let Ok(line) = line else { break };
+
+            if !line.trim().is_empty() && send.send(Utf8PathBuf::from(line.trim())).is_err() {
+                break;
             }
+        }
+    });
 
-            // do allocation after we checked its an audio file
-            let path = path.canonicalize_utf8()?;
+    let every = Duration::from_secs(every);
+    let mut next = Instant::now() + every;
+    // once stdin is closed there is only the walk left to wait for
+    let mut stdin_open = true;
 
-            let ffmpeg_meta = ffmpeg_next::format::input(&path)?;
+    loop {
+        let left = next.saturating_duration_since(Instant::now());
 
-            // metadata() is coming from a private Deref<Target = Context> type...
-            // TODO PR it to not be like this
-            Ok(AudioFile::from_kv_and_path(
-                path,
-                ffmpeg_meta.metadata().iter(),
-            ))
-        })
+        let path = match stdin_open {
+            true => changed.recv_timeout(left),
+            false => {
+                thread::sleep(left);
+                Err(RecvTimeoutError::Timeout)
+            }
+        };
+
+        match path {
+            Ok(path) => {
+                reread(args, index, &mut writer, &reader, &path)?;
+            }
+            Err(RecvTimeoutError::Disconnected) => stdin_open = false,
+            Err(RecvTimeoutError::Timeout) => {
+                rescan(args, index, &mut writer, &reader)?;
+                next = Instant::now() + every;
+            }
+        }
+    }
 }
 
-#[derive(clap::Parser)]
-/// A music search engine utilizing ffmpeg and tantivy to gather and query songs
-struct Args {
-    /// dirs to recurse into to find music
-    #[arg(num_args = 1..)]
-    dir: Vec<Utf8PathBuf>,
+/// the passed dirs, named as the files under each of them are marked
+fn passed_dirs(args: &Args) -> impl Iterator<Item = (&Utf8Path, Option<String>)> {
+    args.dir
+        .iter()
+        .chain(args.library.iter().map(|(_, dir)| dir))
+        .map(|dir| (&**dir, library_name(args, dir)))
 }
 
-struct Hyperlink<H: Display, T: Display> {
-    hyperlink: H,
-    text: T,
+/// walks the passed dirs again for [`serve_index`], committing only if something changed,
+/// returning whether anything did
+fn rescan(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &mut IndexWriter,
+    reader: &IndexReader,
+) -> Result<bool, Error> {
+    let previous = Previous::read(args, index, &reader.searcher())?;
+
+    let removed = remove_missing(index, writer, &previous);
+
+    let added = index_dirs(args, index, writer, passed_dirs(args), Some(&previous))?;
+
+    commit_changes(args, index, writer, reader, &previous, removed, &added)
 }
 
-impl<H: Display, T: Display> Hyperlink<H, T> {
-    fn new(hyperlink: H, text: T) -> Self {
-        Self { hyperlink, text }
-    }
+/// reads the one file at path again for [`serve_index`], as walking whichever passed dir holds it
+/// would, and commits it if it changed. returns whether it did, a path in none of the dirs is left
+/// alone
+fn reread(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &mut IndexWriter,
+    reader: &IndexReader,
+    path: &Utf8Path,
+) -> Result<bool, Error> {
+    let Ok(file) = path.canonicalize_utf8() else {
+        warn!("could not read {path} again, it is read with the next walk if it is still there");
+        return Ok(false);
+    };
+
+    let library = passed_dirs(args)
+        .filter_map(|(dir, library)| Some((dir.canonicalize_utf8().ok()?, library)))
+        .find(|(dir, _)| file.starts_with(dir));
+
+    let Some((_, library)) = library else {
+        warn!("{path} is in none of the dirs the index is kept up to date with, leaving it alone");
+        return Ok(false);
+    };
+
+    debug!("reading {path} again");
+
+    let previous = Previous::read(args, index, &reader.searcher())?;
+
+    let added = index_dirs(args, index, writer, [(path, library)], Some(&previous))?;
+
+    commit_changes(args, index, writer, reader, &previous, 0, &added)
 }
 
-impl<H: Display, T: Display> Display for Hyperlink<H, T> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\",
-            self.hyperlink, self.text
-        )
+/// commits what a walk after previous removed and added, only if it changed something. returns
+/// whether it did
+fn commit_changes(
+    args: &Args,
+    index: &MusicIndex,
+    writer: &mut IndexWriter,
+    reader: &IndexReader,
+    previous: &Previous,
+    removed: usize,
+    added: &Added,
+) -> Result<bool, Error> {
+    // passing over a file changes nothing searched unless it was indexed before, but it is only
+    // remembered as passed over once committed
+    let skipped = skipped_after(args, Some(previous), added);
+    let dropped = added
+        .skipped
+        .keys()
+        .filter(|path| previous.indexed.contains_key(*path))
+        .count();
+
+    // committing nothing would still have every search process reload
+    if removed + added.songs + added.chapters + dropped == 0 && skipped.files == previous.skipped {
+        debug!("nothing changed");
+        return Ok(false);
     }
+
+    index
+        .commit_skipped(writer, skipped)
+        .and_then(|_| reader.reload())
+        .map_err(|e| Error::Index(format!("could not commit the index: {e}")))?;
+
+    info!(
+        "read {} new or changed songs and removed {removed} files",
+        added.songs
+    );
+
+    Ok(true)
 }
 
-fn main() {
-    let args = Args::parse();
+/// adds every index in dirs to the one in into, creating it where there is none, and commits
+fn merge_indexes(dirs: &[Utf8PathBuf], into: &Utf8Path) -> Result<(), Error> {
+    let mut opened = Vec::new();
+
+    for dir in dirs {
+        let (index, opts) = MusicIndex::open_in_dir(dir)
+            .map_err(|e| Error::Index(format!("could not open the index in {dir}: {e}")))?;
 
-    if args.dir.is_empty() {
-        println!("warning: no directories passed");
+        opened.push((dir, index, opts));
     }
 
-    let (scm, map) = HardSchema::schema();
+    // all of them are checked before anything is written to into
+    let (first, _, opts) = &opened[0];
 
-    let index = tantivy::Index::create_in_ram(scm.clone());
+    for (dir, _, other) in &opened[1..] {
+        let differing = opts.differences(other);
+
+        if !differing.is_empty() {
+            return Err(Error::Index(format!(
+                "could not merge {dir} with {first}, it was built with a different {}",
+                differing.join(", ")
+            )));
+        }
+    }
+
+    let (merged, _) = MusicIndex::open_or_create_in_dir(into, opts).map_err(|e| match e {
+        TantivyError::SchemaError(why) => {
+            Error::Index(format!("could not merge into {into}, {why} than {first}"))
+        }
+        e => Error::Index(format!("could not open the index in {into}: {e}")),
+    })?;
 
+    let mut writer = merged
+        .writer()
+        .map_err(|e| Error::Index(format!("could not create the index writer: {e}")))?;
+
+    let mut entries = 0;
+
+    for (dir, index, _) in &opened {
+        entries += merged
+            .merge_from(&writer, index)
+            .map_err(|e| Error::Index(format!("could not merge {dir}: {e}")))?;
+    }
+
+    merged
+        .commit(&mut writer)
+        .map_err(|e| Error::Index(format!("could not commit the index: {e}")))?;
+
+    info!("merged {entries} entries into {into}");
+
+    Ok(())
+}
+
+/// logs go to stderr (around the progress bar) so they never mix with results, by default only
+/// warnings and the song count are shown
+fn init_logging(verbose: u8) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(match verbose {
+            0 => "warn,musicsearch=info",
+            1 => "warn,musicsearch=debug",
+            _ => "info,musicsearch=trace",
+        })
+    });
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| LogWriter)
+        .without_time()
+        .with_target(false)
+        .init();
+}
+
+/// runs the one mode asked for, returning the code to exit with when it finishes normally
+fn run(args: &Args) -> Result<ExitCode, Error> {
     let hostname_own = gethostname::gethostname();
     let hostname = hostname_own.to_str().unwrap_or("");
 
-    index.tokenizers().register(
-        "ngram3",
-        TextAnalyzer::builder(
-            tantivy::tokenizer::NgramTokenizer::new(3, 3, false)
-                .expect("this tokenizer will not error with these arguments"),
-        )
-        .filter(tantivy::tokenizer::LowerCaser)
-        .build(),
-    );
-    let mut writer = index
-        .writer(20_000_000)
-        .expect("this writer will not error with 20mb of storage allocated");
+    if let Some(into) = &args.into {
+        merge_indexes(&args.merge, into)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &args.dump {
+        check_ffmpeg().map_err(Error::Ffmpeg)?;
+
+        let files = read_file(path, &scan_options(args))
+            .map_err(|e| Error::Index(format!("could not read {path}: {e}")))?;
+
+        for file in files {
+            let json = serde_json::to_string_pretty(&file)
+                .map_err(|e| Error::Index(format!("could not print {path}: {e}")))?;
 
-    let mut songs = 0;
+            println!("{json}");
+        }
 
-    for dir in &args.dir {
-        songs += recursive_find_audiofiles(dir)
-            .map(|v| v.map(|f| writer.add_document(f.tantivy_store(&map))))
-            .filter(|v| v.as_ref().is_ok_and(|v| v.is_ok()))
-            .count();
+        return Ok(ExitCode::SUCCESS);
     }
 
-    writer.commit().unwrap();
+    if let Some(every) = args.serve_index {
+        check_ffmpeg().map_err(Error::Ffmpeg)?;
 
-    println!("{songs} songs in index");
+        if args.dir.is_empty() && args.library.is_empty() {
+            return Err(Error::Index(
+                "--serve-index needs dirs to keep the index up to date with".into(),
+            ));
+        }
 
-    drop(writer);
+        let (index, writer) = build_index(args)?;
+        let writer = writer.expect("an index built from dirs has a writer");
 
-    let mut editor = DefaultEditor::new().unwrap();
-    editor.set_auto_add_history(true);
-    editor.set_completion_type(rustyline::CompletionType::List);
+        info!("walking the dirs again every {every}s, search the index with --index-path");
 
-    // unwrap possibly safe because this is ram backed, docs are unclear
-    let reader = index.reader().unwrap();
+        serve_index(args, &index, writer, every)?;
 
-    let qp = QueryParser::for_index(&index, map.all());
+        return Ok(ExitCode::SUCCESS);
+    }
 
-    loop {
-        let line = match editor.readline("> ") {
-            Ok(line) => line,
-            Err(_) => break,
+    let backend = if let Some(addr) = &args.connect {
+        let remote = RemoteIndex::connect(addr)
+            .map_err(|e| Error::Index(format!("could not connect to {addr}: {e}")))?;
+
+        ui::Backend::Remote(remote)
+    } else {
+        // searching a saved index, over `--rpc` or not, reads no files
+        if reads_files(args) {
+            check_ffmpeg().map_err(Error::Ffmpeg)?;
+        }
+
+        let (index, writer) = build_index(args)?;
+
+        if let Some(addr) = &args.serve {
+            drop(writer);
+
+            println!("serving searches on {addr}");
+
+            remote::serve(
+                &index,
+                query_parser(args, &index),
+                args.rank,
+                addr,
+                hostname,
+            )
+            .map_err(|e| Error::Index(format!("could not serve on {addr}: {e}")))?;
+
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        if args.rpc {
+            drop(writer);
+
+            let qp = query_parser(args, &index);
+
+            match args.output_fd.map(output_fd).transpose()? {
+                Some(out) => remote::serve_stdio(&index, qp, args.rank, hostname, out),
+                None => remote::serve_stdio(&index, qp, args.rank, hostname, io::stdout()),
+            }
+            .map_err(|e| Error::Index(format!("could not answer on stdio: {e}")))?;
+
+            return Ok(ExitCode::SUCCESS);
+        }
+
+        let qp = query_parser(args, &index);
+        ui::Backend::local(index, writer, qp)
+            .map_err(|e| Error::Index(format!("could not open the index for reading: {e}")))?
+    };
+
+    if let Some(field) = &args.dump_terms {
+        ui::dump_terms(&backend, field)?;
+
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &args.lookup {
+        let found = ui::lookup(args, &backend, path)?;
+
+        return Ok(if found {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(1)
+        });
+    }
+
+    match &args.query {
+        Some(query) => {
+            let output = args.output_fd.map(output_fd).transpose()?;
+            let found = ui::query_once(args, backend, hostname, query, output)?;
+
+            Ok(if found {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            })
+        }
+        None => {
+            ui::RustylineUI::new(args, backend, hostname).run()?;
+
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // clap exits with 2 itself on usage errors
+    let args = Args::parse();
+
+    init_logging(args.verbose);
+
+    match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+
+            if let Error::Ffmpeg(_) = e {
+                eprintln!("musicsearch needs ffmpeg's libraries, built with its wav demuxer, to read tags. install them or rebuild musicsearch against the installed ones");
+            }
+
+            e.exit_code()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use musicsearch::parse_query;
+
+    use super::*;
+
+    fn args(argv: &[&str]) -> Args {
+        Args::parse_from(["musicsearch"].iter().chain(argv))
+    }
+
+    #[test]
+    fn searching_a_saved_index_leaves_it_writable() {
+        let dir = ScratchDir::new().unwrap();
+        let index_path = dir.path().as_str();
+
+        // the first run creates it, and only searching it after has no writer
+        drop(build_index(&args(&["--index-path", index_path])).unwrap());
+        let (searching, writer) = build_index(&args(&["--index-path", index_path])).unwrap();
+        assert!(writer.is_none());
+
+        // as `--serve-index` starting after the search did
+        let (serving, _) = MusicIndex::open_in_dir(dir.path()).unwrap();
+        let mut writer = serving.writer().unwrap();
+        serving.commit(&mut writer).unwrap();
+
+        let reader = searching.reader().unwrap();
+        reader.reload().unwrap();
+        assert!(searching.committed().unwrap().is_some());
+    }
+
+    #[test]
+    fn passed_over_files_are_remembered_with_their_flags() {
+        let dir = ScratchDir::new().unwrap();
+        let music = dir.path().join("music");
+        fs::create_dir(&music).unwrap();
+        fs::write(music.join("broken.flac"), "not flac").unwrap();
+
+        let index_path = dir.path().join("index");
+        let run = |extra: &[&str]| {
+            let mut argv = vec!["--index-path", index_path.as_str(), music.as_str()];
+            argv.extend(extra);
+            build_index(&args(&argv)).unwrap().0
         };
 
-        let q = qp.parse_query_lenient(&line).0;
+        let index = run(&[]);
+        let skipped = index.skipped().unwrap();
+        assert_eq!(skipped.files.len(), 1);
+        drop(index);
 
-        let start = Instant::now();
+        // passed over again without being read, and only tried once the exclude flags change
+        let index = run(&[]);
+        let previous = Previous::read(&args(&[]), &index, &index.reader().unwrap().searcher());
+        assert_eq!(previous.unwrap().skipped.len(), 1);
 
-        let search = reader.searcher();
-        let top_resp = search.search(&q, &TopDocs::with_limit(15)).unwrap();
+        let changed = args(&["--min-duration", "1:00"]);
+        let previous = Previous::read(&changed, &index, &index.reader().unwrap().searcher());
+        assert!(previous.unwrap().skipped.is_empty());
+    }
+
+    #[test]
+    fn rescans_commit_only_what_changed() {
+        let dir = ScratchDir::new().unwrap();
+        let music = dir.path().join("music");
+        fs::create_dir(&music).unwrap();
+        fs::write(music.join("broken.flac"), "not flac").unwrap();
+
+        let args = args(&[
+            "--index-path",
+            dir.path().join("index").as_str(),
+            music.as_str(),
+        ]);
+        let (index, writer) = build_index(&args).unwrap();
+        let mut writer = writer.unwrap();
+        let reader = index.reader().unwrap();
+
+        // the broken file was passed over when the index was built, and is not read again
+        assert!(!rescan(&args, &index, &mut writer, &reader).unwrap());
+
+        // a new one is read once, and committed so it is remembered
+        fs::write(music.join("broken too.flac"), "not flac").unwrap();
+        assert!(rescan(&args, &index, &mut writer, &reader).unwrap());
+        assert!(!rescan(&args, &index, &mut writer, &reader).unwrap());
+    }
 
-        for (_, address) in top_resp.into_iter().rev() {
-            let retr = AudioFile::tantivy_recall(&map, &search.doc(address).unwrap());
+    #[test]
+    fn reindexing_needs_something_to_read() {
+        let dir = ScratchDir::new().unwrap();
+        let index = dir.path().join("index");
+
+        assert!(
+            Args::try_parse_from(["musicsearch", "--index-path", index.as_str(), "--reindex"])
+                .is_err()
+        );
+        assert!(Args::try_parse_from([
+            "musicsearch",
+            "--index-path",
+            index.as_str(),
+            "--reindex",
+            dir.path().as_str(),
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn ffmpeg_is_only_checked_when_files_are_read() {
+        assert!(!reads_files(&args(&["--index-path", "index"])));
+        assert!(!reads_files(&args(&["--index-path", "index", "--rpc"])));
+        assert!(reads_files(&args(&[
+            "--index-path",
+            "index",
+            "--rpc",
+            "music"
+        ])));
+        assert!(reads_files(&args(&[
+            "--index-path",
+            "index",
+            "--reindex-path",
+            "music"
+        ])));
+    }
+
+    #[test]
+    fn whitespace_is_kept_when_asked() {
+        assert!(scan_options(&args(&[])).normalize_whitespace);
+        assert!(!scan_options(&args(&["--no-normalize-whitespace"])).normalize_whitespace);
+    }
+
+    #[test]
+    fn chapters_are_excluded_by_their_files_length() {
+        let mut chapter = AudioFile::new("/music/mix.flac".into());
+        chapter.start = Some(0.0);
+        chapter.duration = Some(120);
+        chapter.file_duration = Some(3600);
+
+        assert!(!is_excluded(&args(&["--min-duration", "5m"]), &chapter));
+        assert!(is_excluded(&args(&["--max-duration", "10m"]), &chapter));
+
+        // a song on its own goes by its own length
+        chapter.start = None;
+        assert!(is_excluded(&args(&["--min-duration", "5m"]), &chapter));
+    }
 
-            println!(
-                "{}",
-                Hyperlink::new(format_args!("file://{hostname}{}", retr.file_path), &retr)
+    #[test]
+    fn occur_modifiers_hold_under_either_match() {
+        let index = MusicIndex::from_audiofiles(
+            [
+                ("/music/1.flac", "Ambient Dreams", "Radiohead"),
+                ("/music/2.flac", "Ambient Live", "Radiohead"),
+                ("/music/3.flac", "Live at Wembley", "Queen"),
+                ("/music/4.flac", "Ambient Dreams", "Brian Eno"),
+            ]
+            .map(|(path, title, artist)| {
+                AudioFile::from_kv_and_path(
+                    path,
+                    [("title", title), ("artist", artist)].into_iter(),
+                    AudioFile::DEFAULT_MAX_TAG_LEN,
+                )
+            }),
+        )
+        .unwrap();
+        let searcher = index.reader().unwrap().searcher();
+
+        let found = |mode: &str, query: &str| -> Vec<String> {
+            let qp = query_parser(&args(&["--match", mode]), &index);
+            let (q, _) = parse_query(&qp, query);
+
+            let mut paths: Vec<String> = index
+                .search(&searcher, &*q, query, 10, Rank::Bm25, &[])
+                .unwrap()
+                .into_iter()
+                .map(|(_, f)| f.file_path.into_string())
+                .collect();
+            paths.sort();
+            paths
+        };
+
+        for mode in ["any", "all"] {
+            assert_eq!(
+                found(mode, "ambient -live"),
+                ["/music/1.flac", "/music/4.flac"],
+                "{mode}"
             );
         }
 
-        if !line.is_empty() {
-            println!("searched in {:?}", start.elapsed());
+        // only the base differs, `+` is required and the rest optional with any
+        assert_eq!(
+            found("any", "+artist:radiohead dreams"),
+            ["/music/1.flac", "/music/2.flac"]
+        );
+        assert_eq!(found("all", "+artist:radiohead dreams"), ["/music/1.flac"]);
+    }
+
+    #[test]
+    fn output_fds_are_only_for_answers() {
+        assert!(Args::try_parse_from(["musicsearch", "--output-fd", "3"]).is_err());
+        assert!(Args::try_parse_from(["musicsearch", "--output-fd", "3", "--query", "a"]).is_ok());
+        assert!(Args::try_parse_from(["musicsearch", "--output-fd", "3", "--rpc"]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_fds_stay_open_after_writing() {
+        use std::os::fd::AsRawFd;
+
+        let dir = ScratchDir::new().unwrap();
+        let path = dir.path().join("out");
+        let mut passed = fs::File::create(&path).unwrap();
+
+        let mut output = output_fd(passed.as_raw_fd()).unwrap();
+        output.write_all(b"first ").unwrap();
+        drop(output);
+
+        // closing what was written through would have closed out from under passed
+        passed.write_all(b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first second");
+
+        assert!(output_fd(-1).is_err());
+    }
+
+    /// probe_wav with title as its INAM, which ffmpeg reads as the title tag
+    fn titled_wav(title: &str) -> Vec<u8> {
+        let mut name = title.as_bytes().to_vec();
+        name.push(0);
+        let size = name.len() as u32;
+        if name.len() % 2 == 1 {
+            name.push(0);
         }
+
+        let mut list = b"INFO".to_vec();
+        list.extend(b"INAM");
+        list.extend(size.to_le_bytes());
+        list.extend(name);
+
+        // after the header and fmt chunk, before the samples
+        let probe = probe_wav();
+        let mut wav = probe[..36].to_vec();
+        wav.extend(b"LIST");
+        wav.extend((list.len() as u32).to_le_bytes());
+        wav.extend(list);
+        wav.extend(&probe[36..]);
+
+        let riff = (wav.len() - 8) as u32;
+        wav[4..8].copy_from_slice(&riff.to_le_bytes());
+        wav
+    }
+
+    #[test]
+    fn modified_files_are_read_again_on_their_own() {
+        let dir = ScratchDir::new().unwrap();
+        let music = dir.path().join("music");
+        fs::create_dir(&music).unwrap();
+        let song = music.join("song.wav");
+        fs::write(&song, titled_wav("Before")).unwrap();
+        fs::write(music.join("broken.flac"), "not flac").unwrap();
+
+        let args = args(&[
+            "--index-path",
+            dir.path().join("index").as_str(),
+            music.as_str(),
+        ]);
+        let (index, writer) = build_index(&args).unwrap();
+        let mut writer = writer.unwrap();
+        let reader = index.reader().unwrap();
+
+        let titles = |query: &str| -> Vec<String> {
+            let qp = query_parser(&args, &index);
+            let (q, _) = parse_query(&qp, query);
+
+            index
+                .search(&reader.searcher(), &*q, query, 10, Rank::Bm25, &[])
+                .unwrap()
+                .into_iter()
+                .filter_map(|(_, f)| f.title)
+                .collect()
+        };
+        assert_eq!(titles("before"), ["Before"]);
+
+        // as a tag editor saving it, a second later than it was indexed at the least
+        fs::write(&song, titled_wav("After")).unwrap();
+        let later = SystemTime::now() + Duration::from_secs(2);
+        fs::File::options()
+            .write(true)
+            .open(&song)
+            .and_then(|f| f.set_modified(later))
+            .unwrap();
+
+        // what a line of stdin has the serve loop do
+        assert!(reread(&args, &index, &mut writer, &reader, &song).unwrap());
+        assert_eq!(titles("after"), ["After"]);
+        assert!(titles("before").is_empty());
+        assert_eq!(reader.searcher().num_docs(), 1);
+
+        // told again with nothing changed, or of a file it does not keep, nothing is committed
+        assert!(!reread(&args, &index, &mut writer, &reader, &song).unwrap());
+        let elsewhere = dir.path().join("elsewhere.wav");
+        fs::write(&elsewhere, titled_wav("Elsewhere")).unwrap();
+        assert!(!reread(&args, &index, &mut writer, &reader, &elsewhere).unwrap());
     }
 }
@@ -0,0 +1,265 @@
+use std::{borrow::Cow, collections::BTreeMap, fs, io};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::AudioFile;
+
+/// a file listed in a playlist, with whatever the playlist itself says about it
+#[derive(Debug, Clone)]
+pub struct PlaylistEntry {
+    pub path: Utf8PathBuf,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl PlaylistEntry {
+    fn new(base: &Utf8Path, path: &str, display: Option<&str>) -> Option<Self> {
+        let path = match path.split_once("://") {
+            // only local files can be indexed, `file:///a` and `file://localhost/a` both are
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("file") => {
+                let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+
+                if !rest.starts_with('/') {
+                    return None;
+                }
+
+                percent_decode(rest)?
+            }
+            Some(_) => return None,
+            None => Cow::Borrowed(path),
+        };
+
+        // players write both "Artist - Title" and a bare title
+        let (artist, title) = match display.map(str::trim).filter(|d| !d.is_empty()) {
+            Some(d) => match d.split_once(" - ") {
+                Some((artist, title)) => (Some(artist.to_owned()), Some(title.to_owned())),
+                None => (None, Some(d.to_owned())),
+            },
+            None => (None, None),
+        };
+
+        Some(Self {
+            path: base.join(&*path),
+            artist,
+            title,
+        })
+    }
+
+    /// gives file the title and artist the playlist lists it with, where it has none of its own
+    pub(crate) fn fill_in(&self, file: &mut AudioFile) {
+        if file.title.is_none() {
+            file.title.clone_from(&self.title);
+        }
+
+        if file.artist.is_none() && file.album_artist.is_none() {
+            file.artist.clone_from(&self.artist);
+        }
+    }
+}
+
+/// uri with every `%XX` escape (such as `%20` for a space) made the byte it stands for, `%`s not
+/// followed by two hex digits are kept as they are. none if what they decode to is not utf-8
+fn percent_decode(uri: &str) -> Option<Cow<'_, str>> {
+    if !uri.contains('%') {
+        return Some(Cow::Borrowed(uri));
+    }
+
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok().map(Cow::Owned)
+}
+
+/// reads the files an m3u (or m3u8) or pls playlist lists, in order, with relative paths resolved
+/// against the playlist's dir. entries that are not local files, such as streams, are left out
+pub fn read_playlist(path: &Utf8Path) -> io::Result<Vec<PlaylistEntry>> {
+    // older m3us are often latin-1, those names will be mangled but the rest still reads
+    let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+
+    let base = path.parent().unwrap_or(Utf8Path::new(""));
+
+    let is_pls = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("pls"))
+        || text.trim_start().starts_with("[playlist]");
+
+    Ok(if is_pls {
+        read_pls(base, &text)
+    } else {
+        read_m3u(base, &text)
+    })
+}
+
+fn read_m3u(base: &Utf8Path, text: &str) -> Vec<PlaylistEntry> {
+    let mut entries = Vec::new();
+
+    // the display part of an #EXTINF:<duration>,<display> line, which describes the next path
+    let mut display = None;
+
+    for line in text.lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            display = info.split_once(',').map(|(_, d)| d);
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.extend(PlaylistEntry::new(base, line, display.take()));
+        }
+    }
+
+    entries
+}
+
+fn read_pls(base: &Utf8Path, text: &str) -> Vec<PlaylistEntry> {
+    // FileN and TitleN line up by N and may come in any order
+    let mut files: BTreeMap<u32, (Option<&str>, Option<&str>)> = BTreeMap::new();
+
+    for line in text.lines().map(str::trim) {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+
+        let key = key.to_ascii_lowercase();
+
+        if let Some(n) = key.strip_prefix("file").and_then(|n| n.parse().ok()) {
+            files.entry(n).or_default().0 = Some(value);
+        } else if let Some(n) = key.strip_prefix("title").and_then(|n| n.parse().ok()) {
+            files.entry(n).or_default().1 = Some(value);
+        }
+    }
+
+    files
+        .into_values()
+        .filter_map(|(file, title)| PlaylistEntry::new(base, file?, title))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(entries: &[PlaylistEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    #[test]
+    fn m3u_resolves_relative_paths() {
+        let entries = read_m3u(
+            Utf8Path::new("/music/lists"),
+            "#EXTM3U\n../a.flac\n\n  b.mp3  \n/abs/c.ogg\n# a comment\n",
+        );
+
+        assert_eq!(
+            paths(&entries),
+            ["/music/lists/../a.flac", "/music/lists/b.mp3", "/abs/c.ogg"]
+        );
+    }
+
+    #[test]
+    fn m3u_extinf_describes_the_next_path() {
+        let entries = read_m3u(
+            Utf8Path::new("/music"),
+            "#EXTM3U\n\
+             #EXTINF:354,Radiohead - Let Down\n\
+             a.flac\n\
+             b.flac\n\
+             #EXTINF:-1,Untitled Jam\n\
+             c.flac\n",
+        );
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[0].artist.as_deref(), Some("Radiohead"));
+        assert_eq!(entries[0].title.as_deref(), Some("Let Down"));
+
+        // #EXTINF only goes with the one path after it
+        assert_eq!(entries[1].title, None);
+
+        assert_eq!(entries[2].artist, None);
+        assert_eq!(entries[2].title.as_deref(), Some("Untitled Jam"));
+    }
+
+    #[test]
+    fn m3u_leaves_out_streams() {
+        let entries = read_m3u(
+            Utf8Path::new("/music"),
+            "http://example.com/stream\nfile:///music/a.flac\n",
+        );
+
+        assert_eq!(paths(&entries), ["/music/a.flac"]);
+    }
+
+    #[test]
+    fn file_uris_are_percent_decoded() {
+        let entries = read_m3u(
+            Utf8Path::new("/music"),
+            "file:///music/My%20Album/01%20Caf%C3%A9.flac\n\
+             file://localhost/music/b%2fc.flac\n\
+             FILE:///music/100%.flac\n\
+             file://nas/music/remote.flac\n\
+             file:///music/bad%FF.flac\n",
+        );
+
+        assert_eq!(
+            paths(&entries),
+            [
+                "/music/My Album/01 Café.flac",
+                "/music/b/c.flac",
+                "/music/100%.flac"
+            ]
+        );
+    }
+
+    #[test]
+    fn pls_lines_up_files_and_titles() {
+        let entries = read_pls(
+            Utf8Path::new("/music"),
+            "[playlist]\n\
+             Title2=Second\n\
+             File2=b.flac\n\
+             File1=/abs/a.flac\n\
+             Title1=Someone - First\n\
+             NumberOfEntries=2\n\
+             Version=2\n",
+        );
+
+        assert_eq!(paths(&entries), ["/abs/a.flac", "/music/b.flac"]);
+        assert_eq!(entries[0].artist.as_deref(), Some("Someone"));
+        assert_eq!(entries[0].title.as_deref(), Some("First"));
+        assert_eq!(entries[1].title.as_deref(), Some("Second"));
+    }
+
+    #[test]
+    fn playlist_info_only_fills_in_missing_tags() {
+        let entry = PlaylistEntry::new(Utf8Path::new("/music"), "a.flac", Some("A - B")).unwrap();
+
+        let mut untagged = AudioFile::new("/music/a.flac".into());
+        entry.fill_in(&mut untagged);
+        assert_eq!(untagged.artist.as_deref(), Some("A"));
+        assert_eq!(untagged.title.as_deref(), Some("B"));
+
+        let mut tagged = AudioFile::builder("/music/a.flac")
+            .album_artist("Tagged")
+            .title("Tagged Title")
+            .build();
+        entry.fill_in(&mut tagged);
+        assert_eq!(tagged.artist, None);
+        assert_eq!(tagged.title.as_deref(), Some("Tagged Title"));
+    }
+}
@@ -0,0 +1,356 @@
+//! a small line based json protocol so an index built on one machine can be searched from another,
+//! or by another program over stdin, every request and response is a single line of json
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tantivy::{query::QueryParser, IndexReader};
+use tracing::debug;
+
+use crate::{parse_query, AudioFile, MusicIndex, Rank};
+
+/// sent as `{"search": "...", "limit": 10}`, where limit may be left out, or as `"status"`
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "RequestLine", into = "RequestLine")]
+pub enum Request {
+    Search {
+        query: String,
+        limit: usize,
+    },
+    /// asks for a [`Response::Status`], for checking a server is up before searching it
+    Status,
+}
+
+impl Request {
+    /// how many results a search asking for no number of them gets, as many as `--limit` shows
+    pub const DEFAULT_LIMIT: usize = 15;
+}
+
+/// how a [`Request`] is written on the wire
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum RequestLine {
+    Search {
+        search: String,
+        #[serde(default = "default_limit")]
+        limit: usize,
+    },
+    Named(NamedRequest),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NamedRequest {
+    Status,
+}
+
+fn default_limit() -> usize {
+    Request::DEFAULT_LIMIT
+}
+
+impl From<RequestLine> for Request {
+    fn from(line: RequestLine) -> Self {
+        match line {
+            RequestLine::Search { search, limit } => Self::Search {
+                query: search,
+                limit,
+            },
+            RequestLine::Named(NamedRequest::Status) => Self::Status,
+        }
+    }
+}
+
+impl From<Request> for RequestLine {
+    fn from(req: Request) -> Self {
+        match req {
+            Request::Search { query, limit } => Self::Search {
+                search: query,
+                limit,
+            },
+            Request::Status => Self::Named(NamedRequest::Status),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    /// host is the machine the files live on, for building links to them
+    Results {
+        host: String,
+        results: Vec<AudioFile>,
+    },
+    /// ready is always true, as nothing is answered until the index is built, documents counts
+    /// chapters as well as songs and committed is when the index was last committed, in seconds
+    /// since the unix epoch, null for an index last committed by a version that did not record it
+    Status {
+        ready: bool,
+        documents: u64,
+        committed: Option<u64>,
+    },
+    Error(String),
+}
+
+/// answers requests for index on addr until accepting connections fails, each client gets a thread
+pub fn serve(
+    index: &MusicIndex,
+    qp: QueryParser,
+    rank: Rank,
+    addr: impl ToSocketAddrs,
+    host: &str,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    let server = Server::new(index, qp, rank, host)?;
+
+    thread::scope(|s| {
+        for conn in listener.incoming() {
+            let conn = conn?;
+
+            let server = &server;
+
+            // a client hanging up mid request is their problem, not the servers
+            s.spawn(move || _ = server.handle_client(conn));
+        }
+
+        Ok(())
+    })
+}
+
+/// answers requests for index read from stdin on out (normally stdout) until stdin is closed, for
+/// programs that keep musicsearch running as a subprocess instead of paying for indexing on every
+/// search
+pub fn serve_stdio(
+    index: &MusicIndex,
+    qp: QueryParser,
+    rank: Rank,
+    host: &str,
+    out: impl Write,
+) -> io::Result<()> {
+    Server::new(index, qp, rank, host)?.answer(io::stdin(), out)
+}
+
+/// what every client of one index is answered with
+struct Server<'a> {
+    index: &'a MusicIndex,
+    reader: IndexReader,
+    qp: QueryParser,
+    rank: Rank,
+    host: &'a str,
+}
+
+impl<'a> Server<'a> {
+    fn new(index: &'a MusicIndex, qp: QueryParser, rank: Rank, host: &'a str) -> io::Result<Self> {
+        Ok(Self {
+            index,
+            reader: index.reader().map_err(io::Error::other)?,
+            qp,
+            rank,
+            host,
+        })
+    }
+
+    fn handle_client(&self, conn: TcpStream) -> io::Result<()> {
+        let out = conn.try_clone()?;
+
+        self.answer(conn, out)
+    }
+
+    /// answers every request read from input on out, flushing each response so a client waiting
+    /// on it is never left hanging on a buffer
+    fn answer(&self, input: impl Read, mut out: impl Write) -> io::Result<()> {
+        for line in BufReader::new(input).lines() {
+            let resp = match serde_json::from_str(&line?) {
+                Ok(req) => self.respond(req),
+                Err(e) => Response::Error(format!("malformed request: {e}")),
+            };
+
+            serde_json::to_writer(&mut out, &resp)?;
+            out.write_all(b"\n")?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn respond(&self, req: Request) -> Response {
+        let searcher = self.reader.searcher();
+
+        match req {
+            // tantivy panics collecting no results, which would take the whole server down
+            Request::Search { limit: 0, .. } => {
+                Response::Error("limit has to be at least 1".to_owned())
+            }
+            Request::Search { query, limit } => {
+                let (q, ignored) = parse_query(&self.qp, &query);
+
+                for e in ignored {
+                    debug!("ignoring part of {query:?}: {e}");
+                }
+
+                match self
+                    .index
+                    .search(&searcher, &q, &query, limit, self.rank, &[])
+                {
+                    Ok(hits) => Response::Results {
+                        host: self.host.to_owned(),
+                        results: hits.into_iter().map(|(_, file)| file).collect(),
+                    },
+                    Err(e) => Response::Error(e.to_string()),
+                }
+            }
+            Request::Status => match self.index.committed() {
+                Ok(committed) => Response::Status {
+                    ready: true,
+                    documents: searcher.num_docs(),
+                    committed,
+                },
+                Err(e) => Response::Error(e.to_string()),
+            },
+        }
+    }
+}
+
+/// a connection to an index being served by [`serve`]
+pub struct RemoteIndex {
+    conn: BufReader<TcpStream>,
+    host: String,
+}
+
+impl RemoteIndex {
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            conn: BufReader::new(TcpStream::connect(addr)?),
+            host: String::new(),
+        })
+    }
+
+    pub fn request(&mut self, req: &Request) -> io::Result<Response> {
+        let out = self.conn.get_mut();
+
+        serde_json::to_writer(&mut *out, req)?;
+        out.write_all(b"\n")?;
+
+        let mut line = String::new();
+
+        if self.conn.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// results best first, the same as a local search would return them
+    pub fn search(&mut self, query: &str, limit: usize) -> io::Result<Vec<AudioFile>> {
+        let req = Request::Search {
+            query: query.to_owned(),
+            limit,
+        };
+
+        match self.request(&req)? {
+            Response::Results { host, results } => {
+                self.host = host;
+                Ok(results)
+            }
+            Response::Error(e) => Err(io::Error::other(e)),
+            Response::Status { .. } => Err(io::Error::other("answered a search with a status")),
+        }
+    }
+
+    /// hostname of the server as of the last search
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Request {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn search_requests() {
+        let Request::Search { query, limit } = parse(r#"{"search": "abbey road", "limit": 10}"#)
+        else {
+            panic!("not a search");
+        };
+        assert_eq!((&*query, limit), ("abbey road", 10));
+
+        let Request::Search { limit, .. } = parse(r#"{"search": "abbey road"}"#) else {
+            panic!("not a search");
+        };
+        assert_eq!(limit, Request::DEFAULT_LIMIT);
+    }
+
+    #[test]
+    fn status_requests() {
+        assert!(matches!(parse(r#""status""#), Request::Status));
+        assert!(serde_json::from_str::<Request>(r#""restart""#).is_err());
+    }
+
+    #[test]
+    fn requests_round_trip() {
+        let req = Request::Search {
+            query: "x".to_owned(),
+            limit: 3,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"search":"x","limit":3}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Request::Status).unwrap(),
+            r#""status""#
+        );
+    }
+
+    #[test]
+    fn no_limit_is_an_error() {
+        let index =
+            MusicIndex::from_audiofiles([AudioFile::builder("/a.flac").title("abbey").build()])
+                .unwrap();
+        let server = Server::new(&index, index.query_parser(), Rank::Bm25, "host").unwrap();
+
+        let mut out = Vec::new();
+        server
+            .answer(
+                &b"{\"search\": \"abbey\", \"limit\": 0}\n{\"search\": \"abbey\"}\n"[..],
+                &mut out,
+            )
+            .unwrap();
+
+        let mut lines = out.split(|&b| b == b'\n');
+        let first: Response = serde_json::from_slice(lines.next().unwrap()).unwrap();
+        let second: Response = serde_json::from_slice(lines.next().unwrap()).unwrap();
+
+        assert!(matches!(first, Response::Error(_)));
+        assert!(matches!(second, Response::Results { results, .. } if results.len() == 1));
+    }
+
+    #[test]
+    fn status_reports_the_commit() {
+        let index = MusicIndex::from_audiofiles([AudioFile::builder("/a.flac").build()]).unwrap();
+        let committed = index.committed().unwrap();
+        assert!(committed.is_some());
+
+        let server = Server::new(&index, index.query_parser(), Rank::Bm25, "host").unwrap();
+
+        let Response::Status {
+            documents,
+            committed: reported,
+            ..
+        } = server.respond(Request::Status)
+        else {
+            panic!("not a status");
+        };
+        assert_eq!((documents, reported), (1, committed));
+    }
+}
@@ -0,0 +1,461 @@
+use serde::{Deserialize, Serialize};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, TextFieldIndexing, TextOptions, FAST, INDEXED, STORED,
+    STRING, TEXT,
+};
+
+/// knobs for how the schema is built, an index only works with the options it was created with
+#[derive(Default, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SchemaOptions {
+    /// names of text fields to keep for display without indexing them, which shrinks the index
+    /// at the cost of those fields no longer being searchable
+    pub stored_only: Vec<String>,
+
+    /// only take the starts of each word, so queries match from the start of a word instead of
+    /// anywhere in it
+    pub ngram_prefix_only: bool,
+
+    /// lowercased ngrams to leave out of the index and out of queries, for ones so common that
+    /// their postings take up space and time without telling songs apart
+    pub ngram_stop: Vec<String>,
+
+    /// names of text fields whose values are matched whole, ignoring case, instead of by ngrams,
+    /// for tags like genre that are picked from a short list rather than written out
+    pub keyword_fields: Vec<String>,
+
+    /// leave the extras field out of the schema entirely, tags without a field of their own are
+    /// then dropped instead of indexed and stored
+    pub no_extras: bool,
+
+    /// lowercased keys of tags to give fields of their own, named after the key, rather than
+    /// keeping in extras. none may be the name of another field
+    pub promoted_tags: Vec<String>,
+}
+
+impl SchemaOptions {
+    /// these options with each list sorted and without repeats, which is all that matters of them.
+    /// an index is built and stored with these, so passing the same fields in another order is
+    /// not taken for other options
+    pub fn normalized(&self) -> Self {
+        let set = |list: &[String]| {
+            let mut list = list.to_vec();
+            list.sort();
+            list.dedup();
+            list
+        };
+
+        Self {
+            stored_only: set(&self.stored_only),
+            ngram_prefix_only: self.ngram_prefix_only,
+            ngram_stop: set(&self.ngram_stop),
+            keyword_fields: set(&self.keyword_fields),
+            no_extras: self.no_extras,
+            promoted_tags: set(&self.promoted_tags),
+        }
+    }
+
+    /// the names of the options that differ between this and other, however their lists are
+    /// ordered
+    pub fn differences(&self, other: &Self) -> Vec<&'static str> {
+        let other = other.normalized();
+
+        let Self {
+            stored_only,
+            ngram_prefix_only,
+            ngram_stop,
+            keyword_fields,
+            no_extras,
+            promoted_tags,
+        } = self.normalized();
+
+        [
+            ("stored_only", stored_only != other.stored_only),
+            (
+                "ngram_prefix_only",
+                ngram_prefix_only != other.ngram_prefix_only,
+            ),
+            ("ngram_stop", ngram_stop != other.ngram_stop),
+            ("keyword_fields", keyword_fields != other.keyword_fields),
+            ("no_extras", no_extras != other.no_extras),
+            ("promoted_tags", promoted_tags != other.promoted_tags),
+        ]
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+pub struct HardSchema {
+    pub path: Field,
+    pub path_raw: Field,
+    pub artist: Field,
+    pub album_artist: Field,
+    pub album: Field,
+    pub title: Field,
+    pub track: Field,
+    pub date: Field,
+    pub genre: Field,
+    pub work: Field,
+    pub movement: Field,
+    pub movement_number: Field,
+    pub grouping: Field,
+    pub compilation: Field,
+    pub modified: Field,
+    pub start: Field,
+    pub year: Field,
+    pub original_year: Field,
+    pub channels: Field,
+    pub duration: Field,
+    pub chapter_count: Field,
+    pub bpm: Field,
+    pub musical_key: Field,
+    pub rating: Field,
+    pub cover_res: Field,
+    /// none when the schema was built with `no_extras`
+    pub extras: Option<Field>,
+    /// fields of tags promoted out of extras, by tag key, see [`SchemaOptions::promoted_tags`]
+    pub promoted: Vec<(String, Field)>,
+    pub mbid_track: Field,
+    pub mbid_album: Field,
+    pub host: Field,
+    pub library: Field,
+    pub missing: Field,
+    pub item_type: Field,
+    pub values: Field,
+}
+
+impl HardSchema {
+    pub const PATH: &'static str = "path";
+    pub const PATH_RAW: &'static str = "path_raw";
+    pub const ARTIST: &'static str = "artist";
+    pub const ALBUM_ARTIST: &'static str = "album_artist";
+    pub const ALBUM: &'static str = "album";
+    pub const TITLE: &'static str = "title";
+    pub const TRACK: &'static str = "track";
+    pub const DATE: &'static str = "date";
+    pub const GENRE: &'static str = "genre";
+    pub const WORK: &'static str = "work";
+    pub const MOVEMENT: &'static str = "movement";
+    pub const MOVEMENT_NUMBER: &'static str = "movementnumber";
+    pub const GROUPING: &'static str = "grouping";
+    pub const COMPILATION: &'static str = "compilation";
+    pub const MODIFIED: &'static str = "modified";
+    pub const START: &'static str = "start";
+    pub const YEAR: &'static str = "year";
+    pub const ORIGINAL_YEAR: &'static str = "originalyear";
+    pub const CHANNELS: &'static str = "channels";
+    pub const DURATION: &'static str = "duration_secs";
+    pub const CHAPTER_COUNT: &'static str = "chapters";
+    pub const BPM: &'static str = "bpm";
+    pub const MUSICAL_KEY: &'static str = "key";
+    pub const RATING: &'static str = "rating";
+    pub const COVER_RES: &'static str = "coverres";
+    pub const EXTRAS: &'static str = "extras";
+    pub const MBID_TRACK: &'static str = "mbid";
+    pub const MBID_ALBUM: &'static str = "mbid_album";
+    pub const HOST: &'static str = "host";
+    pub const LIBRARY: &'static str = "lib";
+    pub const MISSING: &'static str = "missing";
+    pub const ITEM_TYPE: &'static str = "type";
+    pub const VALUES: &'static str = "values";
+
+    /// name the ngram tokenizer used by every text field must be registered under
+    pub const TOKENIZER: &'static str = "ngram3";
+
+    /// name of the tokenizer text fields use instead with [`SchemaOptions::ngram_prefix_only`]
+    pub const PREFIX_TOKENIZER: &'static str = "word_prefixes";
+
+    /// name the tokenizer of fields matched whole (see [`SchemaOptions::keyword_fields`]) must be
+    /// registered under
+    pub const KEYWORD_TOKENIZER: &'static str = "keyword";
+
+    /// fields whose distinct values can be listed, see [`MusicIndex::for_each_value`]
+    ///
+    /// [`MusicIndex::for_each_value`]: crate::MusicIndex::for_each_value
+    pub const VALUE_FIELDS: &'static [&'static str] = &[
+        HardSchema::ARTIST,
+        HardSchema::ALBUM_ARTIST,
+        HardSchema::ALBUM,
+        HardSchema::TITLE,
+        HardSchema::GENRE,
+        HardSchema::WORK,
+    ];
+
+    /// text fields that may be made stored only
+    pub const TEXT_FIELDS: &'static [&'static str] = &[
+        HardSchema::PATH,
+        HardSchema::ARTIST,
+        HardSchema::ALBUM_ARTIST,
+        HardSchema::ALBUM,
+        HardSchema::TITLE,
+        HardSchema::DATE,
+        HardSchema::GENRE,
+        HardSchema::WORK,
+        HardSchema::MOVEMENT,
+        HardSchema::GROUPING,
+        HardSchema::EXTRAS,
+    ];
+
+    pub fn schema(opts: &SchemaOptions) -> (Schema, Self) {
+        // promoted fields are added in the order they are listed, so the same tags in another
+        // order must still make the same schema
+        let opts = &opts.normalized();
+        let mut schema = Schema::builder();
+
+        let tokenizer = if opts.ngram_prefix_only {
+            HardSchema::PREFIX_TOKENIZER
+        } else {
+            HardSchema::TOKENIZER
+        };
+
+        let text = TEXT.set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(tokenizer)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+        let text_stored = text.clone() | STORED;
+
+        // lowercased but otherwise whole, so genre:rock is not also every genre with rock in it
+        let keyword_stored = TEXT.set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(HardSchema::KEYWORD_TOKENIZER)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        ) | STORED;
+
+        let options = |name: &str| -> TextOptions {
+            if opts.stored_only.iter().any(|f| f == name) {
+                STORED.into()
+            } else if opts.keyword_fields.iter().any(|f| f == name) {
+                keyword_stored.clone()
+            } else {
+                text_stored.clone()
+            }
+        };
+
+        schema.add_text_field(HardSchema::PATH, options(HardSchema::PATH));
+        // the whole path as one term, for looking a file up by exactly its path
+        schema.add_text_field(HardSchema::PATH_RAW, STRING);
+        schema.add_text_field(HardSchema::ARTIST, options(HardSchema::ARTIST));
+        schema.add_text_field(HardSchema::ALBUM_ARTIST, options(HardSchema::ALBUM_ARTIST));
+        schema.add_text_field(HardSchema::ALBUM, options(HardSchema::ALBUM));
+        schema.add_text_field(HardSchema::TITLE, options(HardSchema::TITLE));
+        schema.add_u64_field(HardSchema::TRACK, INDEXED | STORED);
+        schema.add_text_field(HardSchema::DATE, options(HardSchema::DATE));
+        schema.add_text_field(HardSchema::GENRE, options(HardSchema::GENRE));
+        schema.add_text_field(HardSchema::WORK, options(HardSchema::WORK));
+        schema.add_text_field(HardSchema::MOVEMENT, options(HardSchema::MOVEMENT));
+        schema.add_u64_field(HardSchema::MOVEMENT_NUMBER, INDEXED | STORED);
+        schema.add_text_field(HardSchema::GROUPING, options(HardSchema::GROUPING));
+        schema.add_bool_field(HardSchema::COMPILATION, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::MODIFIED, INDEXED | STORED | FAST);
+        schema.add_f64_field(HardSchema::START, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::YEAR, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::ORIGINAL_YEAR, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::CHANNELS, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::DURATION, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::CHAPTER_COUNT, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::BPM, INDEXED | STORED);
+        // keys are short codes like 8A, which ngrams would match against every other 8
+        schema.add_text_field(HardSchema::MUSICAL_KEY, STRING | STORED);
+        schema.add_u64_field(HardSchema::RATING, INDEXED | STORED);
+        schema.add_u64_field(HardSchema::COVER_RES, INDEXED | STORED);
+        // extras are stored for --snippets even though results never show them
+        if !opts.no_extras {
+            schema.add_text_field(HardSchema::EXTRAS, options(HardSchema::EXTRAS));
+        }
+        // ids are matched whole, ngrams of them would match unrelated ids
+        schema.add_text_field(HardSchema::MBID_TRACK, STRING | STORED);
+        schema.add_text_field(HardSchema::MBID_ALBUM, STRING | STORED);
+        // the machine a file was indexed on, whose paths only make sense there
+        schema.add_text_field(HardSchema::HOST, STRING | STORED);
+        // which of the dirs indexed a file came from, named by --library or after the dir
+        schema.add_text_field(HardSchema::LIBRARY, STRING | STORED);
+        // names of the tags a file lacks, absence cannot be queried for otherwise
+        schema.add_text_field(HardSchema::MISSING, STRING);
+        schema.add_text_field(HardSchema::ITEM_TYPE, text_stored.clone());
+        // every value of the VALUE_FIELDS whole as `<field>\0<value>`, so listing the distinct
+        // values of one reads the term dictionary instead of every document
+        schema.add_text_field(HardSchema::VALUES, STRING);
+        // searched like any other tag, only under their own names instead of inside extras
+        for tag in &opts.promoted_tags {
+            schema.add_text_field(tag, text_stored.clone());
+        }
+
+        let scm = schema.build();
+
+        let hard = Self::from_schema(&scm);
+
+        (scm, hard)
+    }
+
+    pub fn all(&self) -> Vec<Field> {
+        let mut all = vec![
+            self.path,
+            self.path_raw,
+            self.artist,
+            self.album_artist,
+            self.album,
+            self.title,
+            self.track,
+            self.date,
+            self.genre,
+            self.work,
+            self.movement,
+            self.movement_number,
+            self.grouping,
+            self.compilation,
+            self.modified,
+            self.start,
+            self.year,
+            self.original_year,
+            self.channels,
+            self.duration,
+            self.chapter_count,
+            self.bpm,
+            self.musical_key,
+            self.rating,
+            self.cover_res,
+            self.mbid_track,
+            self.mbid_album,
+            self.host,
+            self.library,
+            self.missing,
+            self.item_type,
+            self.values,
+        ];
+
+        all.extend(self.extras);
+        all.extend(self.promoted.iter().map(|&(_, field)| field));
+
+        all
+    }
+
+    pub fn from_schema(schema: &Schema) -> Self {
+        // none of these will panic when used on the schema generated by tantivy_schema, extras
+        // is the only field that may be left out of it
+        let mut hard = Self {
+            path: schema.get_field(HardSchema::PATH).unwrap(),
+            path_raw: schema.get_field(HardSchema::PATH_RAW).unwrap(),
+            artist: schema.get_field(HardSchema::ARTIST).unwrap(),
+            album_artist: schema.get_field(HardSchema::ALBUM_ARTIST).unwrap(),
+            album: schema.get_field(HardSchema::ALBUM).unwrap(),
+            title: schema.get_field(HardSchema::TITLE).unwrap(),
+            track: schema.get_field(HardSchema::TRACK).unwrap(),
+            date: schema.get_field(HardSchema::DATE).unwrap(),
+            genre: schema.get_field(HardSchema::GENRE).unwrap(),
+            work: schema.get_field(HardSchema::WORK).unwrap(),
+            movement: schema.get_field(HardSchema::MOVEMENT).unwrap(),
+            movement_number: schema.get_field(HardSchema::MOVEMENT_NUMBER).unwrap(),
+            grouping: schema.get_field(HardSchema::GROUPING).unwrap(),
+            compilation: schema.get_field(HardSchema::COMPILATION).unwrap(),
+            modified: schema.get_field(HardSchema::MODIFIED).unwrap(),
+            start: schema.get_field(HardSchema::START).unwrap(),
+            year: schema.get_field(HardSchema::YEAR).unwrap(),
+            original_year: schema.get_field(HardSchema::ORIGINAL_YEAR).unwrap(),
+            channels: schema.get_field(HardSchema::CHANNELS).unwrap(),
+            duration: schema.get_field(HardSchema::DURATION).unwrap(),
+            chapter_count: schema.get_field(HardSchema::CHAPTER_COUNT).unwrap(),
+            bpm: schema.get_field(HardSchema::BPM).unwrap(),
+            musical_key: schema.get_field(HardSchema::MUSICAL_KEY).unwrap(),
+            rating: schema.get_field(HardSchema::RATING).unwrap(),
+            cover_res: schema.get_field(HardSchema::COVER_RES).unwrap(),
+            extras: schema.get_field(HardSchema::EXTRAS).ok(),
+            mbid_track: schema.get_field(HardSchema::MBID_TRACK).unwrap(),
+            mbid_album: schema.get_field(HardSchema::MBID_ALBUM).unwrap(),
+            host: schema.get_field(HardSchema::HOST).unwrap(),
+            library: schema.get_field(HardSchema::LIBRARY).unwrap(),
+            missing: schema.get_field(HardSchema::MISSING).unwrap(),
+            item_type: schema.get_field(HardSchema::ITEM_TYPE).unwrap(),
+            values: schema.get_field(HardSchema::VALUES).unwrap(),
+            promoted: Vec::new(),
+        };
+
+        // whatever is left over can only be a promoted tag, which is named after its key
+        let known = hard.all();
+        hard.promoted = schema
+            .fields()
+            .filter(|(field, _)| !known.contains(field))
+            .map(|(field, entry)| (entry.name().to_owned(), field))
+            .collect();
+
+        hard
+    }
+
+    /// whether name is already taken by a field, so a tag can not be promoted to it
+    pub fn is_field_name(name: &str) -> bool {
+        let (schema, _) = Self::schema(&SchemaOptions::default());
+
+        schema.get_field(name).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_query, AudioFile, MusicIndex, Rank};
+
+    fn stored_only(names: &[&str]) -> SchemaOptions {
+        SchemaOptions {
+            stored_only: names.iter().map(|&n| n.to_owned()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stored_only_fields_are_stored_but_not_indexed() {
+        let (schema, hard) = HardSchema::schema(&stored_only(&[
+            HardSchema::PATH,
+            HardSchema::DATE,
+            HardSchema::EXTRAS,
+        ]));
+
+        for field in [hard.path, hard.date, hard.extras.unwrap()] {
+            let entry = schema.get_field_entry(field);
+
+            assert!(entry.is_stored(), "{}", entry.name());
+            assert!(!entry.is_indexed(), "{}", entry.name());
+        }
+
+        let title = schema.get_field_entry(hard.title);
+        assert!(title.is_stored() && title.is_indexed());
+    }
+
+    #[test]
+    fn stored_only_fields_round_trip_but_do_not_match() {
+        let index = MusicIndex::create_in_ram(&stored_only(&[HardSchema::DATE]));
+
+        let mut writer = index.writer().unwrap();
+        writer
+            .add_document(
+                AudioFile::builder("/music/a.flac")
+                    .title("Teardrop")
+                    .date("1998-04-20")
+                    .build()
+                    .tantivy_store(index.schema()),
+            )
+            .unwrap();
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let qp = index.query_parser();
+        let search = |text: &str| {
+            index
+                .search(
+                    &searcher,
+                    &*parse_query(&qp, text).0,
+                    text,
+                    10,
+                    Rank::Bm25,
+                    &[],
+                )
+                .unwrap()
+        };
+
+        assert!(search("1998").is_empty());
+
+        let hits = search("teardrop");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].1.date.as_deref(), Some("1998-04-20"));
+    }
+}
@@ -0,0 +1,169 @@
+//! terminal measuring, for keeping each result on a single line
+
+use unicode_width::UnicodeWidthChar;
+
+/// columns of the terminal on stdout, none if stdout is not a terminal
+#[cfg(unix)]
+pub fn width() -> Option<usize> {
+    // SAFETY: winsize is plain data and TIOCGWINSZ only writes into it
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_col == 0 {
+            return None;
+        }
+
+        Some(size.ws_col.into())
+    }
+}
+
+#[cfg(not(unix))]
+pub fn width() -> Option<usize> {
+    None
+}
+
+/// escape sequences are passed through whole without counting towards the width, this covers
+/// CSI (colors) and OSC (hyperlinks) which is all we emit
+fn split_escape(s: &str) -> Option<usize> {
+    let rest = s.strip_prefix('\x1b')?;
+
+    match rest.as_bytes().first()? {
+        b'[' => {
+            let end = rest[1..].find(|c: char| ('\x40'..='\x7e').contains(&c))?;
+            Some(1 + 1 + end + 1)
+        }
+        b']' => {
+            let bel = rest.find('\x07').map(|i| i + 1);
+            let st = rest.find("\x1b\\").map(|i| i + 2);
+
+            let end = match (bel, st) {
+                (Some(a), Some(b)) => a.min(b),
+                (a, b) => a.or(b)?,
+            };
+
+            Some(1 + end)
+        }
+        _ => Some(1),
+    }
+}
+
+enum Piece<'a> {
+    Visible(char),
+    Escape(&'a str),
+}
+
+fn pieces(s: &str) -> impl Iterator<Item = Piece<'_>> {
+    let mut rest = s;
+
+    std::iter::from_fn(move || {
+        let c = rest.chars().next()?;
+
+        let (piece, len) = match split_escape(rest) {
+            Some(len) => {
+                let len = len.min(rest.len());
+                (Piece::Escape(&rest[..len]), len)
+            }
+            None => (Piece::Visible(c), c.len_utf8()),
+        };
+
+        rest = &rest[len..];
+
+        Some(piece)
+    })
+}
+
+pub fn display_width(s: &str) -> usize {
+    pieces(s)
+        .map(|p| match p {
+            Piece::Visible(c) => c.width().unwrap_or(0),
+            Piece::Escape(_) => 0,
+        })
+        .sum()
+}
+
+/// shortens s to at most max columns ending in an ellipsis, escape sequences are all kept so colors
+/// are still reset and hyperlinks still closed after the cut
+pub fn elide(s: &str, max: usize) -> String {
+    if display_width(s) <= max {
+        return s.to_owned();
+    }
+
+    let mut out = String::with_capacity(s.len());
+    let mut used = 0;
+    let mut cut = false;
+
+    for piece in pieces(s) {
+        match piece {
+            Piece::Escape(esc) => out.push_str(esc),
+            Piece::Visible(_) if cut => (),
+            Piece::Visible(c) => {
+                let w = c.width().unwrap_or(0);
+
+                // leave a column for the ellipsis, where there is one at all
+                if used + w + 1 > max {
+                    cut = true;
+
+                    if max > 0 {
+                        out.push('…');
+                    }
+                } else {
+                    used += w;
+                    out.push(c);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widths_count_columns_not_bytes() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("\x1b[31mred\x1b[0m"), 3);
+        assert_eq!(
+            display_width("\x1b]8;;file:///a\x1b\\link\x1b]8;;\x1b\\"),
+            4
+        );
+    }
+
+    #[test]
+    fn elided_lines_keep_their_escapes() {
+        let colored = "\x1b[31mhello world\x1b[0m";
+        assert_eq!(elide(colored, 5), "\x1b[31mhell…\x1b[0m");
+        assert_eq!(elide(colored, 11), colored);
+
+        let link = "\x1b]8;;file:///a\x07hello world\x1b]8;;\x07";
+        assert_eq!(elide(link, 3), "\x1b]8;;file:///a\x07he…\x1b]8;;\x07");
+    }
+
+    #[test]
+    fn wide_chars_are_never_split() {
+        for (max, elided) in [
+            (6, "日本語"),
+            (5, "日本…"),
+            (4, "日…"),
+            (3, "日…"),
+            (2, "…"),
+        ] {
+            let out = elide("日本語", max);
+
+            assert_eq!(out, elided, "{max}");
+            assert!(display_width(&out) <= max, "{max}");
+        }
+    }
+
+    #[test]
+    fn tiny_limits() {
+        assert_eq!(elide("abc", 1), "…");
+        assert_eq!(elide("\x1b[1mabc\x1b[0m", 0), "\x1b[1m\x1b[0m");
+        assert_eq!(elide("", 0), "");
+        assert_eq!(elide("a", 1), "a");
+    }
+}
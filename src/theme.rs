@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+/// the escape sequences each part of a result is colored with
+#[derive(Clone, Copy, Debug)]
+pub struct Theme {
+    /// file names, and anything else that is only there for context
+    pub path: &'static str,
+    /// the `:` and `-` between fields
+    pub separator: &'static str,
+    pub title: &'static str,
+    /// where a chapter starts
+    pub start: &'static str,
+    pub artist: &'static str,
+    pub album: &'static str,
+    pub track: &'static str,
+    pub date: &'static str,
+    pub genre: &'static str,
+    /// the parts of a snippet a query matched
+    pub highlight: &'static str,
+}
+
+impl Theme {
+    pub const DEFAULT: Theme = Theme {
+        path: "\x1b[37m",
+        separator: "\x1b[37m",
+        title: "\x1b[92m",
+        start: "\x1b[37m",
+        artist: "\x1b[92m",
+        album: "\x1b[94m",
+        track: "\x1b[94m",
+        date: "\x1b[32m",
+        genre: "\x1b[35m",
+        highlight: "\x1b[93m",
+    };
+
+    /// no colors, only bold for snippet matches
+    pub const MONO: Theme = Theme {
+        path: "",
+        separator: "",
+        title: "",
+        start: "",
+        artist: "",
+        album: "",
+        track: "",
+        date: "",
+        genre: "",
+        highlight: "\x1b[1m",
+    };
+
+    /// the solarized accent colors, readable on both its light and dark backgrounds
+    pub const SOLARIZED: Theme = Theme {
+        path: "\x1b[38;2;147;161;161m",
+        separator: "\x1b[38;2;147;161;161m",
+        title: "\x1b[38;2;133;153;0m",
+        start: "\x1b[38;2;147;161;161m",
+        artist: "\x1b[38;2;42;161;152m",
+        album: "\x1b[38;2;38;139;210m",
+        track: "\x1b[38;2;108;113;196m",
+        date: "\x1b[38;2;181;137;0m",
+        genre: "\x1b[38;2;211;54;130m",
+        highlight: "\x1b[38;2;203;75;22m",
+    };
+
+    pub const NAMES: &'static [&'static str] = &["default", "mono", "solarized"];
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::DEFAULT),
+            "mono" => Ok(Self::MONO),
+            "solarized" => Ok(Self::SOLARIZED),
+            _ => Err(format!("unknown theme {s:?}")),
+        }
+    }
+}
@@ -0,0 +1,1715 @@
+use core::fmt;
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::HashMap,
+    fmt::Display,
+    io::{self, IsTerminal, Write},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use musicsearch::{
+    extract_cover, extract_entry, literal_query, order_ties, parse_query, remote::Response,
+    split_archive_path, without_leading_article, AudioFile, HardSchema, MusicIndex, RemoteIndex,
+    ScratchDir, ShowField, SortKey, Theme,
+};
+use rustyline::{
+    completion::Completer, config::Configurer, error::ReadlineError, highlight::Highlighter,
+    hint::Hinter, history::DefaultHistory, validate::Validator, Editor, Helper,
+};
+use tantivy::{
+    collector::{Count, TopDocs},
+    query::{BooleanQuery, Occur, Query, QueryParser},
+    schema::Field,
+    snippet::{Snippet, SnippetGenerator},
+    DocAddress, IndexReader, IndexWriter, Order, Score, TantivyError,
+};
+use tracing::{debug, warn};
+
+use crate::{term, Args, GroupBy, Separator};
+
+pub const DEFAULT_OPENER: &str = if cfg!(target_os = "macos") {
+    "open"
+} else if cfg!(windows) {
+    "explorer"
+} else {
+    "xdg-open"
+};
+
+/// builds the command to open path with, the path is always passed as its own argument (or
+/// substituted into one) so spaces in it need no quoting
+fn open_command(template: &str, path: &Utf8Path, start: Option<f64>) -> Option<Command> {
+    let mut parts = shlex::split(template)?;
+
+    if parts.is_empty() {
+        return None;
+    }
+
+    let start = start.unwrap_or(0.0).to_string();
+
+    for p in &mut parts {
+        *p = p.replace("{start}", &start);
+    }
+
+    if parts.iter().any(|p| p.contains("{}")) {
+        for p in &mut parts {
+            *p = p.replace("{}", path.as_str());
+        }
+    } else {
+        parts.push(path.to_string());
+    }
+
+    let mut cmd = Command::new(&parts[0]);
+    cmd.args(&parts[1..]);
+
+    Some(cmd)
+}
+
+fn open_file(template: &str, file: &AudioFile) {
+    let Some((archive, entry)) = split_archive_path(&file.file_path) else {
+        return open_path(template, &file.file_path, file.start);
+    };
+
+    // the player reads the copy after this returns, so it is left in the temporary dir
+    let extracted = ScratchDir::new().and_then(|dir| {
+        let path = extract_entry(archive, entry, dir.path())?;
+        dir.keep();
+
+        Ok(path)
+    });
+
+    match extracted {
+        Ok(path) => open_path(template, &path, file.start),
+        Err(e) => println!("could not extract {entry} from {archive}: {e}"),
+    }
+}
+
+fn open_path(template: &str, path: &Utf8Path, start: Option<f64>) {
+    let Some(mut cmd) = open_command(template, path, start) else {
+        println!("invalid --open-with command {template:?}");
+        return;
+    };
+
+    match cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(mut child) => {
+            // reap it in the background so players can keep running while we search
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => println!("could not run {template:?}: {e}"),
+    }
+}
+
+/// the `file://` url of a path on host. windows paths have their drive letter or unc share moved
+/// into url form and no host, since windows takes a hostname as a network share
+struct FileUrl<'a> {
+    host: &'a str,
+    path: &'a Utf8Path,
+    /// whether path is a windows one, which it is wherever this is built for windows
+    windows: bool,
+}
+
+impl<'a> FileUrl<'a> {
+    fn new(host: &'a str, path: &'a Utf8Path) -> Self {
+        Self {
+            host,
+            path,
+            windows: cfg!(windows),
+        }
+    }
+}
+
+impl Display for FileUrl<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.windows {
+            return write!(
+                f,
+                "file://{}{}",
+                self.host,
+                PercentEncoded(self.path.as_str())
+            );
+        }
+
+        let path = self.path.as_str().replace('\\', "/");
+
+        match path.strip_prefix("//") {
+            // \\server\share\x is file://server/share/x
+            Some(unc) => write!(f, "file://{}", PercentEncoded(unc)),
+            // C:\x is file:///C:/x
+            None => write!(
+                f,
+                "file:///{}",
+                PercentEncoded(path.trim_start_matches('/'))
+            ),
+        }
+    }
+}
+
+/// a url path with every byte that is not allowed as is in one escaped as `%XX`, so spaces, `#`,
+/// `%` and non ascii names survive the trip through the terminal. `/` and `:` (for drive letters)
+/// are left alone
+struct PercentEncoded<'a>(&'a str);
+
+impl Display for PercentEncoded<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &b in self.0.as_bytes() {
+            match b {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'-'
+                | b'.'
+                | b'_'
+                | b'~'
+                | b'/'
+                | b':' => write!(f, "{}", char::from(b))?,
+                _ => write!(f, "%{b:02X}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Hyperlink<H: Display, T: Display> {
+    hyperlink: H,
+    text: T,
+}
+
+impl<H: Display, T: Display> Hyperlink<H, T> {
+    fn new(hyperlink: H, text: T) -> Self {
+        Self { hyperlink, text }
+    }
+}
+
+impl<H: Display, T: Display> Display for Hyperlink<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\",
+            self.hyperlink, self.text
+        )
+    }
+}
+
+/// set by SIGINT while results are being printed, so ctrl-c stops the output instead of the program
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// catches SIGINT into [`INTERRUPTED`] while it lives, putting back whatever handled it before on
+/// drop, so ctrl-c still kills indexing and slow commands
+struct CatchSigint(libc::sighandler_t);
+
+impl CatchSigint {
+    fn new() -> Self {
+        INTERRUPTED.store(false, Ordering::Relaxed);
+
+        // SAFETY: the handler only stores to an atomic, which is async signal safe
+        let previous = unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+
+        Self(previous)
+    }
+}
+
+impl Drop for CatchSigint {
+    fn drop(&mut self) {
+        // SAFETY: this is the handler that was installed before, or the default
+        unsafe {
+            libc::signal(libc::SIGINT, self.0);
+        }
+    }
+}
+
+/// where searches typed into the ui are answered from
+// only one of these ever exists, boxing the local variant would buy nothing
+#[allow(clippy::large_enum_variant)]
+pub enum Backend {
+    Local {
+        index: MusicIndex,
+        reader: IndexReader,
+        qp: QueryParser,
+
+        /// kept open so `:index` can add to the index while searching it. a saved index that is
+        /// only searched has none until `:index` takes one, another process (`--serve-index`)
+        /// may be writing to it meanwhile
+        writer: Option<IndexWriter>,
+    },
+    Remote(RemoteIndex),
+}
+
+impl Backend {
+    pub fn local(
+        index: MusicIndex,
+        writer: Option<IndexWriter>,
+        qp: QueryParser,
+    ) -> tantivy::Result<Self> {
+        let reader = index.reader()?;
+
+        Ok(Self::Local {
+            index,
+            reader,
+            qp,
+            writer,
+        })
+    }
+}
+
+/// shows the best match for what has been typed so far after the cursor, with how many songs
+/// match, without waiting for enter. only for a local index, and only once a few characters have
+/// been typed since anything shorter matches most of the library anyway. lines still being typed
+/// into a quote, bracket or field are not hinted, and neither are keys typed faster than a search
+/// of a large library takes, so typing never waits on a hint
+struct SearchHints {
+    reader: IndexReader,
+    qp: QueryParser,
+    schema: HardSchema,
+
+    /// mirrors the ui's `:literal` state, so the hint is for the search enter would run
+    literal: bool,
+
+    /// lines shorter than this get no hint, at least [`SearchHints::MIN_CHARS`] and otherwise
+    /// `--min-query-len`
+    min_chars: usize,
+
+    /// the last line hinted for and its hint, as moving the cursor asks again for the same line
+    last: RefCell<Option<(String, Option<String>)>>,
+
+    /// after a search slower than [`SearchHints::BUDGET`], keys typed before this get no hint
+    /// rather than each waiting as long for one
+    busy_until: Cell<Option<Instant>>,
+}
+
+impl SearchHints {
+    /// fewest characters a line gets a hint for whatever `--min-query-len` is, shorter ones match
+    /// most of the library
+    const MIN_CHARS: usize = 3;
+
+    /// longest a hint may take before the keys typed right after it are left unhinted
+    const BUDGET: Duration = Duration::from_millis(25);
+
+    fn new(reader: IndexReader, qp: QueryParser, min_query_len: usize) -> Self {
+        let schema = HardSchema::from_schema(reader.searcher().schema());
+
+        Self {
+            reader,
+            qp,
+            schema,
+            literal: false,
+            min_chars: min_query_len.max(Self::MIN_CHARS),
+            last: RefCell::new(None),
+            busy_until: Cell::new(None),
+        }
+    }
+
+    fn search(&self, line: &str) -> Option<String> {
+        let query = if self.literal {
+            literal_query(line)
+        } else {
+            line.to_owned()
+        };
+
+        let searcher = self.reader.searcher();
+        let (q, ignored) = parse_query(&self.qp, &query);
+
+        // a half typed `artist:(` or the like, whatever is left of it is not what enter would find
+        if !ignored.is_empty() {
+            return None;
+        }
+
+        let (count, top) = searcher.search(&q, &(Count, TopDocs::with_limit(1))).ok()?;
+        let (_, address) = top.first()?;
+
+        let shown = [ShowField::Title, ShowField::Artist];
+        let fields: Vec<Field> = shown.iter().flat_map(|f| f.fields(&self.schema)).collect();
+
+        let file =
+            AudioFile::tantivy_recall_only(&self.schema, &searcher.doc(*address).ok()?, &fields);
+        let hint = format!("  {} [{count}]", file.show(&shown, &Theme::MONO));
+
+        let room = term::width()?.saturating_sub(line.chars().count() + 2);
+        Some(term::elide(&hint, room))
+    }
+}
+
+/// whether line has a quote, bracket or field still open, as it does while being typed, which
+/// parses into something other than what it will be once finished
+fn is_unbalanced(line: &str) -> bool {
+    let mut quoted = false;
+    let mut depth = 0i32;
+    let mut escaped = false;
+
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '(' | '[' | '{' if !quoted => depth += 1,
+            ')' | ']' | '}' if !quoted => depth -= 1,
+            _ => {}
+        }
+    }
+
+    quoted || depth != 0 || line.trim_end().ends_with(':')
+}
+
+impl Hinter for SearchHints {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _: &rustyline::Context<'_>) -> Option<String> {
+        if pos < line.len() || line.starts_with(':') || line.trim().chars().count() < self.min_chars
+        {
+            return None;
+        }
+
+        if let Some((last, hint)) = &*self.last.borrow() {
+            if last == line {
+                return hint.clone();
+            }
+        }
+
+        if !self.literal && is_unbalanced(line) {
+            return None;
+        }
+
+        if self
+            .busy_until
+            .get()
+            .is_some_and(|until| Instant::now() < until)
+        {
+            return None;
+        }
+
+        let start = Instant::now();
+        let hint = self.search(line);
+        let took = start.elapsed();
+
+        self.busy_until
+            .set((took > Self::BUDGET).then(|| Instant::now() + took));
+        *self.last.borrow_mut() = Some((line.to_owned(), hint.clone()));
+
+        hint
+    }
+}
+
+impl Highlighter for SearchHints {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        // the result line resets its own styling at the end, which would undim the count
+        let dimmed = hint.replace("\x1b[0m", "\x1b[0m\x1b[2m");
+        Cow::Owned(format!("\x1b[2m{dimmed}\x1b[0m"))
+    }
+}
+
+impl Completer for SearchHints {
+    type Candidate = String;
+}
+
+impl Validator for SearchHints {}
+
+impl Helper for SearchHints {}
+
+/// the interactive prompt, reading searches and commands with rustyline
+pub struct RustylineUI<'a> {
+    args: &'a Args,
+    backend: Backend,
+    hostname: &'a str,
+
+    /// results of the last search by rank, to be acted on by commands
+    last_results: Vec<(usize, AudioFile)>,
+
+    /// whether searches are taken as plain text, starts as `--literal` and is flipped by
+    /// `:literal`
+    literal: bool,
+
+    /// the last search followed by every `:refine` of it, results have to match all of them
+    refinements: Vec<String>,
+
+    /// terms every search leaves out, starts as `--exclude` and is replaced by `:exclude`
+    excluded: Vec<String>,
+
+    /// generation of the local index the last search ran on, which changes with every commit
+    /// to it, so a search can say when there is more to find than there was last time
+    generation: Option<u64>,
+}
+
+impl<'a> RustylineUI<'a> {
+    pub fn new(args: &'a Args, backend: Backend, hostname: &'a str) -> Self {
+        Self {
+            args,
+            backend,
+            hostname,
+            last_results: Vec::new(),
+            literal: args.literal,
+            refinements: Vec::new(),
+            excluded: args.exclude.clone(),
+            generation: None,
+        }
+    }
+
+    pub fn run(mut self) -> Result<(), crate::Error> {
+        let mut editor: Editor<SearchHints, DefaultHistory> = Editor::new()
+            .map_err(|e| crate::Error::Index(format!("could not start the prompt: {e}")))?;
+        editor.set_auto_add_history(true);
+        editor.set_completion_type(rustyline::CompletionType::List);
+
+        if let (true, Backend::Local { reader, qp, .. }) = (self.args.hints, &self.backend) {
+            editor.set_helper(Some(SearchHints::new(
+                reader.clone(),
+                qp.clone(),
+                self.args.min_query_len,
+            )));
+        }
+
+        loop {
+            if let Some(hints) = editor.helper_mut() {
+                hints.literal = self.literal;
+                // :index may have added to what a line matches
+                *hints.last.get_mut() = None;
+            }
+
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => return Ok(()),
+                Err(e) => return Err(crate::Error::Index(format!("could not read input: {e}"))),
+            };
+
+            if let Some(cmd) = line.strip_prefix(':') {
+                let (name, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+
+                self.command(name, arg);
+                continue;
+            }
+
+            let start = Instant::now();
+
+            if self.search(&line) {
+                println!("searched in {:?}", start.elapsed());
+            }
+        }
+    }
+
+    fn command(&mut self, name: &str, arg: &str) {
+        match name {
+            "tokens" => match &self.backend {
+                Backend::Local { index, .. } => println!("{:?}", index.tokens(arg)),
+                Backend::Remote(_) => println!(":tokens is not available on a remote index"),
+            },
+            "open" => match arg.trim().parse::<usize>() {
+                Ok(n) => match self.last_results.iter().find(|(rank, _)| *rank == n) {
+                    Some((_, file)) => open_file(&self.args.open_with, file),
+                    None => println!("no result #{n}"),
+                },
+                Err(_) => println!("usage: :open <result number>"),
+            },
+            "folder" => match arg.trim().parse::<usize>() {
+                Ok(n) => match self.last_results.iter().find(|(rank, _)| *rank == n) {
+                    // a file in an archive is in the archive's folder
+                    Some((_, file)) => match split_archive_path(&file.file_path)
+                        .map_or(&*file.file_path, |(archive, _)| archive)
+                        .parent()
+                    {
+                        Some(dir) => open_path(&self.args.folder_open_with, dir, None),
+                        None => println!("{} is not in a folder", file.file_path),
+                    },
+                    None => println!("no result #{n}"),
+                },
+                Err(_) => println!("usage: :folder <result number>"),
+            },
+            "recent" => self.recent(arg),
+            "index" => self.index(arg),
+            "refine" => self.refine(arg),
+            "cover" => self.cover(arg),
+            "similar" => self.similar(arg),
+            "info" => {
+                if let Err(e) = lookup(self.args, &self.backend, arg) {
+                    println!("{e}");
+                }
+            }
+            "exclude" => self.exclude(arg),
+            "literal" => {
+                self.literal = !self.literal;
+
+                if self.literal {
+                    println!("searches are now plain text");
+                } else {
+                    println!("searches now use query syntax");
+                }
+            }
+            _ => println!("unknown command :{name}"),
+        }
+    }
+
+    /// a new search, which starts over any refinement of the last one, returning whether it was
+    /// long enough to run
+    fn search(&mut self, line: &str) -> bool {
+        self.refinements.clear();
+
+        if line.trim().is_empty() {
+            return false;
+        }
+
+        if line.trim().chars().count() < self.args.min_query_len {
+            println!(
+                "keep typing, searches need at least {} characters",
+                self.args.min_query_len
+            );
+            return false;
+        }
+
+        self.refinements.push(self.query_text(line));
+        self.run_refinements();
+
+        true
+    }
+
+    /// replaces the terms left out of every search with the comma separated terms, or clears them
+    fn exclude(&mut self, terms: &str) {
+        self.excluded = terms
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        if self.excluded.is_empty() {
+            println!("no longer excluding anything");
+        } else {
+            println!("excluding {}", self.excluded.join(", "));
+        }
+    }
+
+    /// narrows the last search down to results that also match terms
+    fn refine(&mut self, terms: &str) {
+        if terms.trim().is_empty() {
+            println!("usage: :refine <terms>");
+            return;
+        }
+
+        if self.refinements.is_empty() {
+            println!("nothing to refine, search for something first");
+            return;
+        }
+
+        self.refinements.push(self.query_text(terms));
+        self.run_refinements();
+    }
+
+    fn query_text(&self, line: &str) -> String {
+        if self.literal {
+            literal_query(line)
+        } else {
+            line.to_owned()
+        }
+    }
+
+    fn run_refinements(&mut self) {
+        if let Backend::Local { reader, .. } = &self.backend {
+            let searcher = reader.searcher();
+            let generation = searcher.generation().generation_id();
+
+            if self.generation.is_some_and(|last| last != generation) {
+                println!(
+                    "\x1b[2mthe index changed since the last search, {} entries now\x1b[0m",
+                    searcher.num_docs()
+                );
+            }
+
+            self.generation = Some(generation);
+        }
+
+        match hits(
+            self.args,
+            &mut self.backend,
+            self.hostname,
+            &self.refinements,
+            &self.excluded,
+            true,
+        ) {
+            Ok((host, hits)) if self.args.header => {
+                // the count is only known once every hit is, so this gives up streaming them
+                let hits: Vec<_> = hits.collect();
+
+                println!(
+                    "\x1b[2m{} · {} results\x1b[0m",
+                    self.refinements.join(" · "),
+                    hits.len()
+                );
+
+                show(self.args, host, hits.into_iter(), &mut self.last_results);
+            }
+            Ok((host, hits)) => show(self.args, host, hits, &mut self.last_results),
+            Err(e) => println!("search failed: {e}"),
+        }
+    }
+
+    /// writes the cover art of a result out to a file, or to a temporary one that then gets opened
+    fn cover(&self, arg: &str) {
+        let (n, out) = arg.trim().split_once(' ').unwrap_or((arg.trim(), ""));
+
+        let Ok(n) = n.parse::<usize>() else {
+            println!("usage: :cover <result number> [file]");
+            return;
+        };
+
+        let Some((_, file)) = self.last_results.iter().find(|(rank, _)| *rank == n) else {
+            println!("no result #{n}");
+            return;
+        };
+
+        if let Backend::Remote(_) = self.backend {
+            println!(":cover is not available on a remote index");
+            return;
+        }
+
+        let cover = match split_archive_path(&file.file_path) {
+            Some((archive, entry)) => ScratchDir::new()
+                .and_then(|dir| extract_cover(&extract_entry(archive, entry, dir.path())?)),
+            None => extract_cover(&file.file_path),
+        };
+
+        let (data, ext) = match cover {
+            Ok(Some(cover)) => cover,
+            Ok(None) => {
+                println!("{} has no embedded cover art", file.file_path);
+                return;
+            }
+            Err(e) => {
+                println!("could not read {}: {e}", file.file_path);
+                return;
+            }
+        };
+
+        let (out, open) = match out.trim() {
+            "" => {
+                let stem = file.file_path.file_stem().unwrap_or("cover");
+
+                // the viewer reads it after this returns, so like an extracted entry it is left
+                // in a fresh dir of its own, where nothing else can be waiting at its path
+                let written = ScratchDir::new().and_then(|dir| {
+                    let path = dir.path().join(format!("{stem}.{ext}"));
+
+                    std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)?
+                        .write_all(&data)?;
+                    dir.keep();
+
+                    Ok(path)
+                });
+
+                match written {
+                    Ok(path) => (path, true),
+                    Err(e) => {
+                        println!("could not write the cover art, pass a file instead: {e}");
+                        return;
+                    }
+                }
+            }
+            out => {
+                let out = Utf8PathBuf::from(out);
+
+                if let Err(e) = std::fs::write(&out, data) {
+                    println!("could not write {out}: {e}");
+                    return;
+                }
+
+                (out, false)
+            }
+        };
+
+        println!("wrote cover art to {out}");
+
+        if open {
+            open_path(&self.args.open_with, &out, None);
+        }
+    }
+
+    /// walks another directory into the live index, so searches see it right after
+    fn index(&mut self, arg: &str) {
+        let dir = arg.trim();
+
+        if dir.is_empty() {
+            println!("usage: :index <dir>");
+            return;
+        }
+
+        let Backend::Local {
+            index,
+            reader,
+            writer,
+            ..
+        } = &mut self.backend
+        else {
+            println!(":index is not available on a remote index");
+            return;
+        };
+
+        let writer = match writer {
+            Some(writer) => writer,
+            None => match index.writer() {
+                Ok(new) => writer.insert(new),
+                Err(TantivyError::LockFailure(..)) => {
+                    println!(
+                        ":index is not available while another musicsearch writes to the index"
+                    );
+                    return;
+                }
+                Err(e) => {
+                    println!("could not write to the index: {e}");
+                    return;
+                }
+            },
+        };
+
+        let dir = Utf8PathBuf::from(dir);
+
+        if !dir.is_dir() {
+            println!("{dir} is not a directory");
+            return;
+        }
+
+        // not checked at startup when there was nothing to read then
+        if let Err(e) = crate::check_ffmpeg() {
+            println!("{e}");
+            return;
+        }
+
+        // files already indexed are only read again where they changed, so a dir can be indexed
+        // again to pick up edits without doubling it
+        let previous = match crate::Previous::read(self.args, index, &reader.searcher()) {
+            Ok(previous) => previous,
+            Err(e) => {
+                println!("{e}");
+                return;
+            }
+        };
+
+        let added = match crate::index_dirs(
+            self.args,
+            index,
+            writer,
+            [(&*dir, crate::library_name(self.args, &dir))],
+            Some(&previous),
+        ) {
+            Ok(added) => added,
+            Err(e) => {
+                println!("could not add {dir} to the index: {e}");
+
+                // or the next :index would commit whatever of dir was added before the error
+                if let Err(e) = writer.rollback() {
+                    println!("could not undo adding {dir}: {e}");
+                }
+                return;
+            }
+        };
+
+        let skipped = crate::skipped_after(self.args, Some(&previous), &added);
+
+        if let Err(e) = index
+            .commit_skipped(writer, skipped)
+            .and_then(|_| reader.reload())
+        {
+            println!("could not add {dir} to the index: {e}");
+            _ = writer.rollback();
+            return;
+        }
+
+        let crate::Added {
+            songs,
+            chapters,
+            excluded,
+            ..
+        } = added;
+
+        if self.args.chapters {
+            println!("added {songs} songs and {chapters} chapters from {dir}");
+        } else {
+            println!("added {songs} songs from {dir}");
+        }
+
+        if excluded > 0 {
+            println!("{excluded} excluded by {}", crate::EXCLUDE_FLAGS);
+        }
+    }
+
+    /// lists files with tags much like those of a result
+    fn similar(&mut self, arg: &str) {
+        let Ok(n) = arg.trim().parse::<usize>() else {
+            println!("usage: :similar <result number>");
+            return;
+        };
+
+        let Some((_, file)) = self.last_results.iter().find(|(rank, _)| *rank == n) else {
+            println!("no result #{n}");
+            return;
+        };
+
+        let Backend::Local { index, reader, .. } = &self.backend else {
+            println!(":similar is not available on a remote index");
+            return;
+        };
+
+        let searcher = reader.searcher();
+
+        // results are recalled with only what is shown, which may not be every tag compared
+        let file = match index.lookup(&searcher, file.file_path.as_str()) {
+            Ok(files) => files.into_iter().find(|f| f.start == file.start),
+            Err(e) => {
+                println!("could not find similar files: {e}");
+                return;
+            }
+        };
+
+        let Some(file) = file else {
+            println!("result #{n} is no longer indexed");
+            return;
+        };
+
+        let files = match index.similar(&searcher, &file, self.args.limit) {
+            Ok(files) => files,
+            Err(e) => {
+                println!("could not find similar files: {e}");
+                return;
+            }
+        };
+
+        show(
+            self.args,
+            self.hostname,
+            files
+                .into_iter()
+                .enumerate()
+                .rev()
+                .map(|(i, f)| Hit::new(i + 1, f)),
+            &mut self.last_results,
+        );
+    }
+
+    /// lists the most recently modified files, ignoring any query
+    fn recent(&mut self, arg: &str) {
+        let n = match arg.trim() {
+            "" => self.args.limit,
+            n => match n.parse() {
+                Ok(n) => n,
+                Err(_) => {
+                    println!("usage: :recent [count]");
+                    return;
+                }
+            },
+        };
+
+        let Backend::Local { index, reader, .. } = &self.backend else {
+            println!(":recent is not available on a remote index");
+            return;
+        };
+
+        let files = match index.recent(&reader.searcher(), n) {
+            Ok(files) => files,
+            Err(e) => {
+                println!("could not list recent files: {e}");
+                return;
+            }
+        };
+
+        show(
+            self.args,
+            self.hostname,
+            files
+                .into_iter()
+                .enumerate()
+                .rev()
+                .map(|(i, f)| Hit::new(i + 1, f)),
+            &mut self.last_results,
+        );
+    }
+}
+
+/// one search result as it is shown
+pub struct Hit {
+    rank: usize,
+    file: AudioFile,
+
+    /// highlighted excerpts of matched fields that are not shown in full, only with `--snippets`
+    snippets: Vec<String>,
+}
+
+impl Hit {
+    fn new(rank: usize, file: AudioFile) -> Self {
+        Self {
+            rank,
+            file,
+            snippets: Vec::new(),
+        }
+    }
+}
+
+type Hits<'b> = Box<dyn Iterator<Item = Hit> + 'b>;
+
+/// the excluded terms that apply to a search for line, as phrases so `live` rules out songs
+/// containing "live" rather than any sharing an ngram with it. a term line itself has in it is
+/// taken as asked for and left out
+fn exclusions(line: &str, excluded: &[String]) -> Vec<String> {
+    let line = line.to_lowercase();
+
+    excluded
+        .iter()
+        .map(|term| term.trim())
+        .filter(|term| !term.is_empty() && !line.contains(&term.to_lowercase()))
+        .map(|term| format!("\"{}\"", term.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect()
+}
+
+/// a query matching every one of queries and none of excluded, along with the parts of them that
+/// could not be parsed and were left out
+fn local_query(
+    qp: &QueryParser,
+    queries: &[String],
+    excluded: &[String],
+) -> (Box<dyn Query>, Vec<String>) {
+    let mut ignored = Vec::new();
+
+    let mut parse = |text: &str| {
+        let (query, errors) = parse_query(qp, text);
+        ignored.extend(errors.into_iter().map(|e| format!("{text:?}: {e}")));
+        query
+    };
+
+    let query = match (queries, excluded) {
+        ([query], []) => parse(query),
+        _ => {
+            let mut clauses: Vec<(Occur, Box<dyn Query>)> = queries
+                .iter()
+                .map(|query| (Occur::Must, parse(query)))
+                .collect();
+            clauses.extend(excluded.iter().map(|term| (Occur::MustNot, parse(term))));
+
+            Box::new(BooleanQuery::new(clauses))
+        }
+    };
+
+    (query, ignored)
+}
+
+/// how a query was parsed with `--explain-query`, along with whatever of it was ignored, which is
+/// otherwise only logged
+fn explain(args: &Args, query: &dyn Query, ignored: &[String]) {
+    if args.explain_query {
+        eprintln!("{query:#?}");
+    }
+
+    for part in ignored {
+        if args.explain_query {
+            eprintln!("ignored part of {part}");
+        } else {
+            debug!("ignoring part of {part}");
+        }
+    }
+}
+
+/// the fields results are recalled with when they are only shown, what the shown fields read and
+/// whatever ordering, deduplicating and grouping them reads too. start and track break ties
+fn shown_fields(args: &Args, schema: &HardSchema) -> Vec<Field> {
+    let mut fields = vec![schema.start, schema.track];
+
+    fields.extend(args.show_fields.iter().flat_map(|f| f.fields(schema)));
+    fields.extend(args.sort.iter().flat_map(|(key, _)| key.fields(schema)));
+
+    if args.dedup_results {
+        fields.extend([
+            schema.mbid_track,
+            schema.artist,
+            schema.album_artist,
+            schema.title,
+        ]);
+    }
+
+    if args.group_by.is_some() {
+        fields.extend([
+            schema.album,
+            schema.artist,
+            schema.album_artist,
+            schema.compilation,
+        ]);
+    }
+
+    fields
+}
+
+/// runs a search and returns the host results are from with its hits worst first,
+/// local results are only recalled as they are iterated. with shown_only they are recalled with
+/// just what showing them needs, see [`shown_fields`]
+fn hits<'b>(
+    args: &Args,
+    backend: &'b mut Backend,
+    hostname: &'b str,
+    queries: &[String],
+    excluded: &[String],
+    shown_only: bool,
+) -> Result<(&'b str, Hits<'b>), crate::Error> {
+    // exact ranking compares against what was first searched for, refinements only narrow it
+    let line = queries.first().map_or("", String::as_str);
+
+    let excluded = exclusions(line, excluded);
+
+    let (host, hits): (&str, Hits) = match backend {
+        Backend::Local {
+            index, reader, qp, ..
+        } => {
+            let (q, ignored) = local_query(qp, queries, &excluded);
+            explain(args, &*q, &ignored);
+
+            let search = reader.searcher();
+
+            let start = Instant::now();
+            let top_resp = index.top_docs(&search, &q, line, args.limit, args.rank)?;
+            debug!("{} hits for {q:?} in {:?}", top_resp.len(), start.elapsed());
+
+            let schema = index.schema();
+            let preserve_order = args.preserve_order;
+            let recalled = shown_only.then(|| shown_fields(args, schema));
+
+            // the path only has its file name shown and extras are not shown at all, so those
+            // are the fields that can match without it being visible why
+            let snippets: Vec<(&str, SnippetGenerator)> = if args.snippets {
+                [
+                    (HardSchema::PATH, Some(schema.path)),
+                    (HardSchema::EXTRAS, schema.extras),
+                ]
+                .into_iter()
+                .filter_map(|(name, field)| {
+                    let field = field?;
+                    let mut generator = SnippetGenerator::create(&search, &*q, field).ok()?;
+                    generator.set_max_num_chars(SNIPPET_CHARS);
+
+                    Some((name, generator))
+                })
+                .collect()
+            } else {
+                Vec::new()
+            };
+
+            let theme = args.theme;
+
+            // hits that tie on score are recalled together so they can be put in a stable
+            // order, everything else still streams one at a time
+            let mut runs: Vec<(usize, Score, Vec<DocAddress>)> = Vec::new();
+
+            for (i, &(score, address)) in top_resp.iter().enumerate() {
+                match runs.last_mut() {
+                    Some((_, tied, run)) if *tied == score => run.push(address),
+                    _ => runs.push((i, score, vec![address])),
+                }
+            }
+
+            (
+                hostname,
+                Box::new(runs.into_iter().rev().flat_map(move |(start, score, run)| {
+                    let mut files: Vec<(Score, (AudioFile, Vec<String>))> = run
+                        .into_iter()
+                        .filter_map(|address| {
+                            // one unreadable document should not take the rest of the results
+                            // with it
+                            let doc = search
+                                .doc(address)
+                                .inspect_err(|e| warn!("could not read a result: {e}"))
+                                .ok()?;
+
+                            let snippets = snippets
+                                .iter()
+                                .map(|(name, generator)| (name, generator.snippet_from_doc(&doc)))
+                                .filter(|(_, snippet)| !snippet.is_empty())
+                                .map(|(name, snippet)| {
+                                    format!("{name}: {}", highlight(&snippet, &theme))
+                                })
+                                .collect();
+
+                            let file = match &recalled {
+                                Some(fields) => {
+                                    AudioFile::tantivy_recall_only(schema, &doc, fields)
+                                }
+                                None => AudioFile::tantivy_recall(schema, &doc),
+                            };
+
+                            Some((score, (file, snippets)))
+                        })
+                        .collect();
+
+                    order_ties(&mut files, |(file, _)| file, preserve_order);
+
+                    files
+                        .into_iter()
+                        .enumerate()
+                        .rev()
+                        .map(move |(i, (_, (file, snippets)))| Hit {
+                            rank: start + i + 1,
+                            file,
+                            snippets,
+                        })
+                })),
+            )
+        }
+        Backend::Remote(remote) => {
+            // the server only takes one query, so refinements are sent as required groups
+            let mut query = match queries {
+                [query] => query.clone(),
+                _ => queries
+                    .iter()
+                    .map(|query| format!("+({query})"))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            };
+
+            for term in &excluded {
+                query.push_str(&format!(" -{term}"));
+            }
+
+            let files = remote.search(&query, args.limit)?;
+
+            (
+                remote.host(),
+                Box::new(
+                    files
+                        .into_iter()
+                        .enumerate()
+                        .rev()
+                        .map(|(i, f)| Hit::new(i + 1, f)),
+                ),
+            )
+        }
+    };
+
+    if !args.dedup_results && args.sort.is_empty() {
+        return Ok((host, hits));
+    }
+
+    let mut hits: Vec<Hit> = hits.collect();
+
+    if args.dedup_results {
+        hits = dedup_songs(hits);
+    }
+
+    if !args.sort.is_empty() {
+        hits = sort_hits(hits, &args.sort);
+    }
+
+    Ok((host, Box::new(hits.into_iter())))
+}
+
+/// orders hits (worst first) by each key in turn for `--sort`, keeping them worst first with ranks
+/// renumbered to match. the sort is stable, so how well they matched breaks any ties left
+fn sort_hits(mut hits: Vec<Hit>, sort: &[(SortKey, Order)]) -> Vec<Hit> {
+    hits.sort_by_key(|hit| hit.rank);
+
+    hits.sort_by(|a, b| SortKey::cmp_by(sort, &a.file, &b.file));
+
+    for (i, hit) in hits.iter_mut().enumerate() {
+        hit.rank = i + 1;
+    }
+
+    hits.reverse();
+
+    hits
+}
+
+/// extensions of formats that lose nothing, preferred when one song turns up several times
+const LOSSLESS_EXT: &[&str] = &["flac", "alac", "ape", "wav", "aiff", "aif", "wv", "tta"];
+
+/// what makes two results the same song for `--dedup-results`, its musicbrainz id if it has one
+/// and otherwise its artist and title with case and spacing ignored. songs without a title are
+/// never the same as anything
+fn song_key(file: &AudioFile) -> Option<String> {
+    if let Some(mbid) = &file.mbid_track {
+        return Some(mbid.clone());
+    }
+
+    let normalize = |s: &str| {
+        s.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_lowercase()
+    };
+
+    let artist = file.artist.as_deref().or(file.album_artist.as_deref());
+
+    Some(format!(
+        "{}\0{}",
+        normalize(artist.unwrap_or("")),
+        normalize(file.title.as_deref()?)
+    ))
+}
+
+/// keeps one of every song among hits (worst first), a lossless copy if there is one or else the
+/// best ranked, and renumbers what is left so ranks have no gaps
+fn dedup_songs(hits: Vec<Hit>) -> Vec<Hit> {
+    let is_lossless = |hit: &Hit| {
+        hit.file
+            .file_path
+            .extension()
+            .is_some_and(|ext| LOSSLESS_EXT.iter().any(|l| ext.eq_ignore_ascii_case(l)))
+    };
+
+    // the copy kept for each song, lossless first and then the lowest rank
+    let mut kept: HashMap<String, (bool, Reverse<usize>)> = HashMap::new();
+
+    for hit in &hits {
+        if let Some(key) = song_key(&hit.file) {
+            let candidate = (is_lossless(hit), Reverse(hit.rank));
+            let best = kept.entry(key).or_insert(candidate);
+
+            *best = (*best).max(candidate);
+        }
+    }
+
+    let mut hits: Vec<Hit> = hits
+        .into_iter()
+        .filter(|hit| song_key(&hit.file).is_none_or(|key| kept[&key].1 == Reverse(hit.rank)))
+        .collect();
+
+    let n = hits.len();
+    for (i, hit) in hits.iter_mut().enumerate() {
+        hit.rank = n - i;
+    }
+
+    hits
+}
+
+/// longest a snippet may be, enough for some context around a match on one line
+const SNIPPET_CHARS: usize = 80;
+
+/// renders a snippet with its matched ranges picked out
+fn highlight(snippet: &Snippet, theme: &Theme) -> String {
+    let fragment = snippet.fragment();
+
+    let mut out = String::new();
+    let mut shown = 0;
+
+    // ngrams of a match overlap each other, so neighbouring ranges are merged as they go
+    for range in snippet.highlighted() {
+        if range.end <= shown {
+            continue;
+        }
+
+        let start = range.start.max(shown);
+
+        out.push_str(&fragment[shown..start]);
+        out.push_str(theme.highlight);
+        out.push_str(&fragment[start..range.end]);
+        // reset first, a theme may highlight with bold or the like that a color does not undo
+        out.push_str("\x1b[0m");
+        out.push_str(theme.path);
+
+        shown = range.end;
+    }
+
+    out.push_str(&fragment[shown..]);
+
+    out
+}
+
+/// answers a single `--query` and returns whether anything matched, printing results like the ui
+/// does on a terminal and one path per line, best first, when piped. with output they are instead
+/// written there as a line of json, the same as `--rpc` responds with
+pub fn query_once(
+    args: &Args,
+    mut backend: Backend,
+    hostname: &str,
+    query: &str,
+    output: Option<impl Write>,
+) -> Result<bool, crate::Error> {
+    let query = &if args.literal {
+        literal_query(query)
+    } else {
+        query.to_owned()
+    };
+
+    if args.count {
+        // clap keeps --count and --connect apart, a remote index has no way to count
+        let Backend::Local { reader, qp, .. } = &backend else {
+            return Ok(false);
+        };
+
+        let (q, ignored) = local_query(
+            qp,
+            std::slice::from_ref(query),
+            &exclusions(query, &args.exclude),
+        );
+        explain(args, &*q, &ignored);
+
+        let n = reader.searcher().search(&q, &Count)?;
+
+        println!("{n}");
+
+        return Ok(n > 0);
+    }
+
+    let (host, hits) = hits(
+        args,
+        &mut backend,
+        hostname,
+        std::slice::from_ref(query),
+        &args.exclude,
+        output.is_none(),
+    )?;
+    let mut hits = hits.peekable();
+
+    let found = hits.peek().is_some();
+
+    if let Some(mut out) = output {
+        let mut hits: Vec<_> = hits.collect();
+        hits.sort_by_key(|hit| hit.rank);
+
+        let resp = Response::Results {
+            host: host.to_owned(),
+            results: hits.into_iter().map(|hit| hit.file).collect(),
+        };
+
+        serde_json::to_writer(&mut out, &resp)
+            .map_err(|e| crate::Error::Index(format!("could not write results: {e}")))?;
+        writeln!(out)?;
+
+        return Ok(found);
+    }
+
+    if io::stdout().is_terminal() {
+        show(args, host, hits, &mut Vec::new());
+        return Ok(found);
+    }
+
+    let mut hits: Vec<_> = hits.collect();
+    hits.sort_by_key(|hit| hit.rank);
+
+    let mut stdout = io::stdout().lock();
+
+    for hit in hits {
+        writeln!(stdout, "{}", hit.file.file_path)?;
+    }
+
+    Ok(found)
+}
+
+/// the album and album artist a file is grouped under, files without an album are not grouped
+fn album_of(file: &AudioFile, args: &Args) -> Option<(String, Option<String>)> {
+    let artist = file
+        .album_artist_name(&args.various_artists_label)
+        .map(|a| {
+            if args.ignore_leading_article {
+                without_leading_article(a)
+            } else {
+                a
+            }
+        });
+
+    Some((file.album.clone()?, artist.map(str::to_owned)))
+}
+
+/// reorders hits so each album's songs are next to each other, keeping their order within it, and
+/// puts every album where its last (best) hit was
+fn group_by_album(hits: Vec<Hit>, args: &Args) -> Vec<Hit> {
+    let albums: Vec<_> = hits.iter().map(|hit| album_of(&hit.file, args)).collect();
+
+    let last: HashMap<&(String, Option<String>), usize> = albums
+        .iter()
+        .enumerate()
+        .filter_map(|(i, album)| Some((album.as_ref()?, i)))
+        .collect();
+
+    let mut hits: Vec<(usize, Hit)> = hits
+        .into_iter()
+        .zip(&albums)
+        .enumerate()
+        .map(|(i, (hit, album))| (album.as_ref().map_or(i, |a| last[a]), hit))
+        .collect();
+
+    hits.sort_by_key(|(position, _)| *position);
+
+    hits.into_iter().map(|(_, hit)| hit).collect()
+}
+
+/// prints every indexed field of the file at path as json, returning whether it is indexed
+pub fn lookup(args: &Args, backend: &Backend, path: &str) -> Result<bool, crate::Error> {
+    let path = path.trim();
+
+    if path.is_empty() {
+        println!("usage: :info <path>");
+        return Ok(false);
+    }
+
+    let Backend::Local { index, reader, .. } = backend else {
+        return Err(crate::Error::Index(
+            "looking up a path is not available on a remote index".into(),
+        ));
+    };
+
+    // paths are indexed canonical, a relative or symlinked one is looked up as that
+    let path = Utf8Path::new(path);
+    let path = if args.canonicalize {
+        path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned())
+    } else {
+        path.to_owned()
+    };
+
+    let files = index.lookup(&reader.searcher(), path.as_str())?;
+
+    if files.is_empty() {
+        println!("{path} is not indexed");
+    }
+
+    for file in &files {
+        let json = serde_json::to_string_pretty(file)
+            .map_err(|e| crate::Error::Index(format!("could not print {path}: {e}")))?;
+
+        println!("{json}");
+    }
+
+    Ok(!files.is_empty())
+}
+
+/// prints every distinct value of a field one per line, for feeding completions elsewhere
+pub fn dump_terms(backend: &Backend, field: &str) -> Result<(), crate::Error> {
+    let Backend::Local { index, reader, .. } = backend else {
+        return Err(crate::Error::Index(
+            "listing terms is not available on a remote index".into(),
+        ));
+    };
+
+    let mut out = io::stdout().lock();
+
+    // a closed pipe, as from `| head`, is where listing stops rather than an error
+    index.for_each_value(&reader.searcher(), field, |value| {
+        writeln!(out, "{value}").is_ok()
+    })?;
+
+    Ok(())
+}
+
+/// how long a `--separator rule` is when the terminal width is unknown or not truncated to
+const RULE_WIDTH: usize = 40;
+
+/// prints hits as they come, replacing last_results with whatever got printed
+fn show(
+    args: &Args,
+    host: &str,
+    hits: impl Iterator<Item = Hit>,
+    last_results: &mut Vec<(usize, AudioFile)>,
+) {
+    let width = term::width().filter(|_| !args.no_truncate);
+
+    let grouped = args.group_by.is_some();
+
+    let hits: Box<dyn Iterator<Item = Hit>> = match args.group_by {
+        Some(GroupBy::Album) => Box::new(group_by_album(hits.collect(), args).into_iter()),
+        None => Box::new(hits),
+    };
+
+    // the header already names the album, so songs under one leave it out
+    let grouped_fields: Vec<ShowField> = args
+        .show_fields
+        .iter()
+        .copied()
+        .filter(|&f| f != ShowField::Album)
+        .collect();
+
+    // only while printing, rustyline reads ctrl-c as a key while editing and anything else
+    // (such as `:index`) should still be killed by it
+    let _sigint = CatchSigint::new();
+    let mut stdout = io::stdout().lock();
+
+    let mut current_album = None;
+
+    last_results.clear();
+
+    // print each result as soon as its recalled so large limits stream instead of stalling
+    for Hit {
+        rank,
+        file: retr,
+        snippets,
+    } in hits
+    {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            _ = writeln!(stdout, "interrupted");
+            break;
+        }
+
+        let album = grouped.then(|| album_of(&retr, args)).flatten();
+
+        // the key may have had an article taken off, the header names the album as tagged
+        let header = match (&retr.album, &album) {
+            (Some(name), Some(_)) if current_album.as_ref() != album.as_ref() => {
+                Some(match retr.album_artist_name(&args.various_artists_label) {
+                    Some(artist) => format!(
+                        "{}{name}\x1b[0m by {}{artist}\x1b[0m",
+                        args.theme.album, args.theme.artist
+                    ),
+                    None => format!("{}{name}\x1b[0m", args.theme.album),
+                })
+            }
+            _ => None,
+        };
+
+        let fields = if album.is_some() {
+            &grouped_fields
+        } else {
+            &args.show_fields
+        };
+
+        current_album = album;
+
+        // last_results is only empty until the first result is printed
+        if !last_results.is_empty() {
+            _ = match args.separator {
+                Separator::None => Ok(()),
+                Separator::Blank => writeln!(stdout),
+                Separator::Rule => writeln!(
+                    stdout,
+                    "\x1b[2m{}\x1b[0m",
+                    "─".repeat(width.unwrap_or(RULE_WIDTH))
+                ),
+            };
+        }
+
+        let line = format!(
+            "{rank:>2}. {}",
+            Hyperlink::new(
+                FileUrl::new(
+                    host,
+                    // a link can not point inside an archive, but it can point at it
+                    split_archive_path(&retr.file_path)
+                        .map_or(&*retr.file_path, |(archive, _)| archive)
+                ),
+                retr.show(fields, &args.theme)
+            )
+        );
+
+        for line in header.into_iter().chain([line]).chain(
+            snippets
+                .into_iter()
+                .map(|s| format!("      {}{s}\x1b[0m", args.theme.path)),
+        ) {
+            _ = match width {
+                Some(width) => writeln!(stdout, "{}", term::elide(&line, width)),
+                None => writeln!(stdout, "{line}"),
+            };
+        }
+        _ = stdout.flush();
+
+        last_results.push((rank, retr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn preserved_order_lists_an_album_by_track() {
+        let files = [
+            (1, "Come Together"),
+            (10, "Because"),
+            (2, "Something"),
+            (3, "Maxwell"),
+        ]
+        .map(|(track, title)| {
+            AudioFile::from_kv_and_path(
+                format!("/music/Abbey Road/{track} {title}.flac"),
+                [
+                    ("album", "Abbey Road"),
+                    ("title", title),
+                    ("track", &*track.to_string()),
+                ]
+                .into_iter(),
+                AudioFile::DEFAULT_MAX_TAG_LEN,
+            )
+        });
+
+        let tracks = |argv: &[&str]| -> Vec<u64> {
+            let args = Args::parse_from(["musicsearch"].iter().chain(argv));
+            let index = MusicIndex::from_audiofiles(files.clone()).unwrap();
+            let qp = index.query_parser();
+            let mut backend = Backend::local(index, None, qp).unwrap();
+
+            let (_, found) =
+                hits(&args, &mut backend, "", &["album:abbey".into()], &[], true).unwrap();
+            let mut found: Vec<Hit> = found.collect();
+            found.sort_by_key(|hit| hit.rank);
+
+            found.into_iter().filter_map(|hit| hit.file.track).collect()
+        };
+
+        // the file names sort 10 before 2
+        assert_eq!(tracks(&[]), [1, 10, 2, 3]);
+        assert_eq!(tracks(&["--preserve-order"]), [1, 2, 3, 10]);
+    }
+
+    #[test]
+    fn paths_are_percent_encoded() {
+        assert_eq!(
+            PercentEncoded("/music/My Band/01 #1 100%.flac").to_string(),
+            "/music/My%20Band/01%20%231%20100%25.flac"
+        );
+        assert_eq!(
+            PercentEncoded("/música/Björk/ヨ.flac").to_string(),
+            "/m%C3%BAsica/Bj%C3%B6rk/%E3%83%A8.flac"
+        );
+        assert_eq!(PercentEncoded("C:/a-b_c.~d").to_string(), "C:/a-b_c.~d");
+    }
+
+    #[test]
+    fn file_urls_of_every_path_shape() {
+        let url = |path: &str, windows| {
+            FileUrl {
+                host: "box",
+                path: Utf8Path::new(path),
+                windows,
+            }
+            .to_string()
+        };
+
+        assert_eq!(
+            url("/music/My Band/Björk.flac", false),
+            "file://box/music/My%20Band/Bj%C3%B6rk.flac"
+        );
+        assert_eq!(
+            url(r"C:\Music\My Band\Björk.flac", true),
+            "file:///C:/Music/My%20Band/Bj%C3%B6rk.flac"
+        );
+        assert_eq!(
+            url(r"\\nas\share\My Band\ヨ.flac", true),
+            "file://nas/share/My%20Band/%E3%83%A8.flac"
+        );
+    }
+
+    #[test]
+    fn half_typed_queries_are_unbalanced() {
+        for line in ["artist:(", "\"abbey ro", "bpm:[120 TO", "genre:", "title: "] {
+            assert!(is_unbalanced(line), "{line}");
+        }
+
+        for line in [
+            "abbey road",
+            "artist:(beatles stones)",
+            "\"abbey road\"",
+            "bpm:[120 TO 130]",
+            "\\\"quote",
+        ] {
+            assert!(!is_unbalanced(line), "{line}");
+        }
+    }
+}